@@ -0,0 +1,281 @@
+//! This module is an alternative to [crate::surface_nets] that meshes chunks with Dual Contouring.
+//! Like surface nets it places one vertex in every cell the surface passes trough, but the vertex
+//! is positioned using the surface normals, which keeps sharp edges and corners of man-made
+//! structures intact. The meshes use the same grid, layout and offset as surface nets
+
+use crate::{
+    surface_nets::{clear_buffer, fill_grid, has_surface, to_chunk_space, Grid, SurroundingChunks},
+    ChunkData, ChunkMap, ChunkPosition, Shape,
+};
+
+use bevy::prelude::{Mat3, Query, Vec3};
+use fast_surface_nets::{SignedDistance, SurfaceNetsBuffer};
+
+/// The highest minimum corner of a meshed cell, which is the same range surface nets meshes
+const MAX_CELL: u32 = Grid::BOUNDS - 2;
+
+/// How strongly the vertex is pulled toward the average of the surface crossings, this keeps the
+/// solution stable on flat surfaces where the normals don't determine a single point
+const BIAS: f32 = 0.05;
+
+/// Generate the mesh for a chunk with Dual Contouring, like [crate::surface_nets::generate_chunk].
+/// Returns false when the chunk has no mesh
+pub fn generate_chunk_dc(
+    buffer: &mut SurfaceNetsBuffer,
+    data: &mut SurroundingChunks,
+    grid: &mut Grid,
+    chunk_pos: ChunkPosition,
+    chunk_map: &ChunkMap,
+    query: &Query<&ChunkData>,
+) -> bool {
+    if !has_surface(chunk_pos, chunk_map, query) {
+        grid.clear();
+        clear_buffer(buffer);
+        return false;
+    }
+
+    data.fill(chunk_pos, chunk_map, query);
+    fill_grid(grid, data);
+    dual_contour(grid, buffer);
+    to_chunk_space(buffer);
+
+    !buffer.indices.is_empty()
+}
+
+/// Place a vertex in every cell with a surface crossing, then connect the vertices around every
+/// edge with a sign change with a quad
+fn dual_contour(grid: &Grid, buffer: &mut SurfaceNetsBuffer) {
+    clear_buffer(buffer);
+    buffer.stride_to_index.resize(grid.len(), u32::MAX);
+
+    let values: Vec<f32> = grid.iter().map(|v| f32::from(*v)).collect();
+    let value = |p: [u32; 3]| values[Grid::SHAPE.linearize(p) as usize];
+    let negative = |p: [u32; 3]| grid[Grid::SHAPE.linearize(p) as usize].is_negative();
+    let gradient = |p: [u32; 3]| {
+        let mut gradient = Vec3::ZERO;
+        for axis in 0..3 {
+            let (mut low, mut high) = (p, p);
+            low[axis] = low[axis].saturating_sub(1);
+            high[axis] = (high[axis] + 1).min(Grid::BOUNDS - 1);
+            gradient[axis] = (value(high) - value(low)) / (high[axis] - low[axis]) as f32;
+        }
+        gradient
+    };
+
+    for z in 0..=MAX_CELL {
+        for y in 0..=MAX_CELL {
+            for x in 0..=MAX_CELL {
+                let corner = [x, y, z];
+                let corners: [[u32; 3]; 8] = std::array::from_fn(|i| {
+                    let i = i as u32;
+                    [x + (i & 1), y + ((i >> 1) & 1), z + (i >> 2)]
+                });
+                let inside = corners.map(negative);
+                if inside.iter().all(|v| *v == inside[0]) {
+                    continue;
+                }
+
+                let mut ata = Mat3::ZERO;
+                let mut atb = Vec3::ZERO;
+                let mut mass = Vec3::ZERO;
+                let mut normal = Vec3::ZERO;
+                let mut crossings = 0;
+                for (a, b) in EDGES {
+                    if inside[a] == inside[b] {
+                        continue;
+                    }
+                    let (value_a, value_b) = (value(corners[a]), value(corners[b]));
+                    let t = (value_a / (value_a - value_b)).clamp(0., 1.);
+                    let point = cell_offset(a).lerp(cell_offset(b), t);
+                    let n = gradient(corners[a])
+                        .lerp(gradient(corners[b]), t)
+                        .normalize_or_zero();
+
+                    ata += Mat3::from_cols(n * n.x, n * n.y, n * n.z);
+                    atb += n * n.dot(point);
+                    mass += point;
+                    normal += n;
+                    crossings += 1;
+                }
+
+                let mass = mass / crossings as f32;
+                let a = ata + Mat3::from_diagonal(Vec3::splat(BIAS));
+                let vertex = if a.determinant().abs() > f32::EPSILON {
+                    a.inverse() * (atb + mass * BIAS)
+                } else {
+                    mass
+                };
+                let vertex =
+                    Vec3::from(corner.map(|v| v as f32)) + vertex.clamp(Vec3::ZERO, Vec3::ONE);
+
+                let stride = Grid::SHAPE.linearize(corner);
+                buffer.stride_to_index[stride as usize] = buffer.positions.len() as u32;
+                buffer.positions.push(vertex.to_array());
+                buffer.normals.push(normal.normalize_or_zero().to_array());
+                buffer.surface_points.push(corner);
+                buffer.surface_strides.push(stride);
+            }
+        }
+    }
+
+    for i in 0..buffer.surface_points.len() {
+        let corner = buffer.surface_points[i];
+        for axis in 0..3 {
+            let (b, c) = ((axis + 1) % 3, (axis + 2) % 3);
+            if corner[b] == 0 || corner[c] == 0 {
+                continue;
+            }
+            let mut end = corner;
+            end[axis] += 1;
+            let (inside, inside_end) = (negative(corner), negative(end));
+            if inside == inside_end {
+                continue;
+            }
+
+            let cell = |db: u32, dc: u32| {
+                let mut cell = corner;
+                cell[b] -= db;
+                cell[c] -= dc;
+                buffer.stride_to_index[Grid::SHAPE.linearize(cell) as usize]
+            };
+            // Counter-clockwise when seen from outside the surface
+            let quad = if inside {
+                [cell(0, 0), cell(1, 0), cell(1, 1), cell(0, 1)]
+            } else {
+                [cell(0, 1), cell(1, 1), cell(1, 0), cell(0, 0)]
+            };
+            buffer
+                .indices
+                .extend_from_slice(&[quad[0], quad[1], quad[2], quad[0], quad[2], quad[3]]);
+        }
+    }
+}
+
+/// The 12 edges of a cell as pairs of corners, corner i has the offset from [cell_offset]
+const EDGES: [(usize, usize); 12] = [
+    (0, 1),
+    (2, 3),
+    (4, 5),
+    (6, 7),
+    (0, 2),
+    (1, 3),
+    (4, 6),
+    (5, 7),
+    (0, 4),
+    (1, 5),
+    (2, 6),
+    (3, 7),
+];
+
+/// The offset of corner i from the minimum corner of a cell
+fn cell_offset(i: usize) -> Vec3 {
+    Vec3::new((i & 1) as f32, ((i >> 1) & 1) as f32, (i >> 2) as f32)
+}
+
+#[test]
+fn test_watertight_box() {
+    use crate::{
+        edit::BoxSdf, edit::SignedDistanceFunction, RawChunk, Voxel, CHUNK_SIDES, VOXEL_SIZE,
+    };
+    use bevy::{ecs::system::SystemState, prelude::World, utils::HashMap};
+
+    // A box in the middle of the chunk, away from its borders
+    let sdf = BoxSdf(Vec3::new(3., 2., 2.5));
+    let mut chunk = RawChunk::air();
+    for x in 0..CHUNK_SIDES as u32 {
+        for y in 0..CHUNK_SIDES as u32 {
+            for z in 0..CHUNK_SIDES as u32 {
+                let pos = (Vec3::new(x as f32, y as f32, z as f32) - 9.5) * VOXEL_SIZE;
+                let value = sdf.sdf(pos).clamp(-1., 1.);
+                chunk.set_voxel(x, y, z, Voxel::new(1, 0).with_value_f32(value));
+            }
+        }
+    }
+
+    let mut world = World::default();
+    let mut chunk_map = ChunkMap::default();
+    let pos = ChunkPosition::new(0, 0, 0);
+    let entity = world.spawn((pos, ChunkData::from(chunk))).id();
+    chunk_map.insert(pos, entity);
+    let mut state: SystemState<Query<&ChunkData>> = SystemState::new(&mut world);
+    let query = state.get(&world);
+
+    let mut buffer = SurfaceNetsBuffer::default();
+    assert!(generate_chunk_dc(
+        &mut buffer,
+        &mut SurroundingChunks::default(),
+        &mut Grid::default(),
+        pos,
+        &chunk_map,
+        &query,
+    ));
+    assert_eq!(buffer.positions.len(), buffer.normals.len());
+
+    // In a closed mesh with consistent winding every edge is used once in each direction
+    let mut edges = HashMap::<(u32, u32), u32>::default();
+    for triangle in buffer.indices.chunks_exact(3) {
+        for i in 0..3 {
+            let (a, b) = (triangle[i], triangle[(i + 1) % 3]);
+            assert_ne!(a, b);
+            *edges.entry((a, b)).or_default() += 1;
+        }
+    }
+    for ((a, b), count) in &edges {
+        assert_eq!(1, *count);
+        assert_eq!(Some(&1), edges.get(&(*b, *a)));
+    }
+
+    // The box is 6 by 4 by 5 meters, its corners stay sharp
+    let (min, max) = buffer
+        .positions
+        .iter()
+        .fold((Vec3::INFINITY, Vec3::NEG_INFINITY), |(min, max), pos| {
+            (min.min(Vec3::from(*pos)), max.max(Vec3::from(*pos)))
+        });
+    assert!((max - min - Vec3::new(6., 4., 5.)).abs().max_element() < 0.2);
+}
+
+#[test]
+fn test_reused_buffer() {
+    use crate::{RawChunk, Voxel, CHUNK_BOUNDS};
+    use bevy::{ecs::system::SystemState, prelude::World};
+
+    let mut world = World::default();
+    let mut chunk_map = ChunkMap::default();
+    let mut chunk = RawChunk::air();
+    for x in 0..CHUNK_BOUNDS {
+        for y in 0..10 {
+            for z in 0..CHUNK_BOUNDS {
+                chunk.set_voxel(x, y, z, Voxel::new(3, Voxel::MAX_VALUE));
+            }
+        }
+    }
+    let entity = world.spawn(ChunkData::from(chunk)).id();
+    chunk_map.insert(ChunkPosition::new(0, 0, 0), entity);
+
+    let mut state: SystemState<Query<&ChunkData>> = SystemState::new(&mut world);
+    let query = state.get(&world);
+    let mut buffer = SurfaceNetsBuffer::default();
+    let mut data = SurroundingChunks::default();
+    let mut grid = Grid::default();
+    let mut mesh = |chunk_pos| {
+        generate_chunk_dc(
+            &mut buffer,
+            &mut data,
+            &mut grid,
+            chunk_pos,
+            &chunk_map,
+            &query,
+        )
+    };
+
+    assert!(mesh(ChunkPosition::new(0, 0, 0)));
+    // The air chunk is skipped before meshing, which must not leave the previous surface behind
+    assert!(!mesh(ChunkPosition::new(5, 5, 5)));
+    assert!(buffer.positions.is_empty());
+    assert!(buffer.normals.is_empty());
+    assert!(buffer.indices.is_empty());
+    assert!(buffer.surface_points.is_empty());
+    assert!(buffer.surface_strides.is_empty());
+    assert!(buffer.stride_to_index.is_empty());
+}
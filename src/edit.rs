@@ -1,6 +1,10 @@
 //! This module contains logic to edit the voxel grid
 
-use crate::{ChunkData, ChunkMap, ChunkPosition, RawChunk, Voxel, CHUNK_SIDES, VOXEL_SIZE};
+use crate::{
+    surface_nets::{mesh_grid, Grid, SurfaceNetsBuffer, SurroundingChunks},
+    ChunkData, ChunkMap, ChunkPosition, RawChunk, Shape, Voxel, VoxelSize, CHUNK_SIDES,
+    FETCH_SHAPE, VOXEL_SIZE,
+};
 
 use bevy::{prelude::*, utils::HashMap};
 use enum_dispatch::enum_dispatch;
@@ -9,69 +13,116 @@ use serde::{Deserialize, Serialize};
 struct ModifiedChunk {
     entity: Option<Entity>,
     index: usize,
+    /// Whether any voxel of the chunk changed, chunks that were only loaded aren't applied
+    changed: bool,
 }
 
 /// A type to apply modifications to the voxel grid. For optimal performance all ready terrain
-/// modifications should be applied with the same ChunkModifier at once
+/// modifications should be applied with the same ChunkModifier at once. Edits accumulate on the
+/// expanded chunks, so a chunk is only expanded the first time an edit touches it and only
+/// compressed once in [ChunkModifier::apply]. This makes it cheap to collect a whole brush stroke
+/// of overlapping edits before applying them
 #[derive(Default)]
 pub struct ChunkModifier {
     modified: HashMap<ChunkPosition, ModifiedChunk>,
     chunks: Vec<RawChunk>,
+    smooth_mode: SmoothMode,
+    voxel_size: VoxelSize,
 }
 
 impl ChunkModifier {
-    /// Apply the calculated modifications to the bevy [World] trough [Commands]
-    pub fn apply(&self, commands: &mut Commands) {
-        for (pos, data) in self.modified.iter() {
-            let entity = data.entity;
-            let data = &self.chunks[data.index];
+    /// Apply the calculated modifications to the bevy [World] trough [Commands]. Every modified
+    /// chunk is compressed once, no matter how many edits touched it. Chunks where no stored voxel
+    /// changed are skipped, so they don't need to be remeshed. Chunks are updated in order of
+    /// their [ChunkPosition], so the same edits always result in the same commands. Newly spawned
+    /// chunks are added to the [ChunkMap] right away, so following edits write to them instead of
+    /// spawning another chunk, and positions that were marked as air lose their mark
+    pub fn apply(&self, chunk_map: &mut ChunkMap, commands: &mut Commands) {
+        for (pos, entity, data) in self.compress() {
+            chunk_map.unmark_air(pos);
             if let Some(entity) = entity {
-                commands.entity(entity).insert(ChunkData::from(data));
+                commands.entity(entity).insert(data);
             } else {
-                commands.spawn((*pos, ChunkData::from(data)));
+                let entity = commands.spawn((pos, data)).id();
+                chunk_map.insert(pos, entity);
             }
         }
     }
 
-    fn get_voxel(
-        &mut self,
-        chunk_pos: ChunkPosition,
-        chunk_map: &mut ChunkMap,
-        mut chunks_getter: impl FnMut(Entity) -> RawChunk,
-        relative_x: i32,
-        relative_y: i32,
-        relative_z: i32,
-    ) -> Option<&mut Voxel> {
-        const SIZE: i32 = CHUNK_SIDES as i32;
-        let mut chunk_pos = IVec3::new(
-            chunk_pos[0] as i32,
-            chunk_pos[1] as i32,
-            chunk_pos[2] as i32,
-        );
-        let orig_pos = chunk_pos;
-        chunk_pos += IVec3::new(
-            (relative_x as f32 / SIZE as f32).floor() as i32,
-            (relative_y as f32 / SIZE as f32).floor() as i32,
-            (relative_z as f32 / SIZE as f32).floor() as i32,
-        );
+    /// Compress every changed chunk, ordered by position, together with its entity if it already
+    /// exists
+    fn compress(&self) -> Vec<(ChunkPosition, Option<Entity>, ChunkData)> {
+        let mut modified: Vec<_> = self.modified.iter().filter(|(_, m)| m.changed).collect();
+        modified.sort_unstable_by_key(|(pos, _)| **pos);
+        modified
+            .into_iter()
+            .map(|(pos, m)| (*pos, m.entity, ChunkData::from(&self.chunks[m.index])))
+            .collect()
+    }
 
-        if chunk_pos.x < i8::MIN as i32
-            || chunk_pos.x > i8::MAX as i32
-            || chunk_pos.y < i8::MIN as i32
-            || chunk_pos.y > i8::MAX as i32
-            || chunk_pos.z < i8::MIN as i32
-            || chunk_pos.z > i8::MAX as i32
-        {
-            return None;
+    /// Remove all modifications, while keeping the allocated memory around. A cleared modifier
+    /// behaves the same as a new one, so it can be reused for the next batch of edits
+    pub fn clear(&mut self) {
+        self.modified.clear();
+        self.chunks.clear();
+    }
+
+    /// Set the function used to smoothly blend edits with the existing terrain, see [SmoothMode].
+    /// This applies to all following edits, and is kept when the modifier is cleared
+    pub fn set_smooth_mode(&mut self, smooth_mode: SmoothMode) {
+        self.smooth_mode = smooth_mode;
+    }
+
+    /// Set the size of the voxels, which decides which voxels a shape covers, see [VoxelSize]. This
+    /// applies to all following edits, and is kept when the modifier is cleared
+    pub fn set_voxel_size(&mut self, voxel_size: VoxelSize) {
+        self.voxel_size = voxel_size;
+    }
+
+    /// Get the modified data of a chunk, if it was touched by any of the applied edits
+    pub fn modified_chunk(&self, chunk_pos: ChunkPosition) -> Option<&RawChunk> {
+        let index = self.modified.get(&chunk_pos)?.index;
+        Some(&self.chunks[index])
+    }
+
+    /// Mesh a chunk the way it will look after [ChunkModifier::apply], without changing the world.
+    /// The pending data of the modified chunks is combined with the stored data of the other
+    /// neighbors in the chunk map, so editors can show a preview of an edit before it is
+    /// committed. The mesh uses the [VoxelSize] of the modifier, so it matches the mesh made with
+    /// [crate::surface_nets::generate_chunk_sized] for the same size. The buffer is empty if the
+    /// chunk has no mesh
+    pub fn preview_mesh(
+        &self,
+        chunk_pos: ChunkPosition,
+        chunk_map: &ChunkMap,
+        query: &Query<&ChunkData>,
+    ) -> SurfaceNetsBuffer {
+        let mut data = SurroundingChunks::default();
+        data.fill(chunk_pos, chunk_map, query);
+        for x in -1..=1 {
+            for y in -1..=1 {
+                for z in -1..=1 {
+                    if let Some(chunk) = self.modified_chunk(chunk_pos + [x, y, z]) {
+                        data.set_chunk([x, y, z], RawChunk(chunk.0.clone()));
+                    }
+                }
+            }
         }
 
-        let offset = orig_pos - chunk_pos;
-        let chunk_pos = ChunkPosition::new(chunk_pos.x as i8, chunk_pos.y as i8, chunk_pos.z as i8);
-        let relative_x = relative_x + offset.x * SIZE;
-        let relative_y = relative_y + offset.y * SIZE;
-        let relative_z = relative_z + offset.z * SIZE;
+        let mut buffer = SurfaceNetsBuffer::default();
+        mesh_grid(&mut buffer, &data, &mut Grid::default(), self.voxel_size);
+        buffer
+    }
 
-        let chunk_data = match self.modified.get_mut(&chunk_pos) {
+    /// Get the modified data of the chunk at the specified position, loading it trough the
+    /// chunks_getter or creating an air chunk if it was not modified yet
+    fn get_chunk(
+        &mut self,
+        chunk_pos: ChunkPosition,
+        chunk_map: &mut ChunkMap,
+        mut chunks_getter: impl FnMut(Entity) -> RawChunk,
+    ) -> &mut RawChunk {
+        match self.modified.get_mut(&chunk_pos) {
             Some(chunk) => &mut self.chunks[chunk.index],
             None => {
                 let (chunk_entity, chunk_data) = match chunk_map.get(&chunk_pos) {
@@ -85,207 +136,2593 @@ impl ChunkModifier {
                     ModifiedChunk {
                         entity: chunk_entity,
                         index,
+                        changed: false,
                     },
                 );
                 &mut self.chunks[index]
             }
+        }
+    }
+
+    /// Apply a [SignedDistanceFunction] to the voxel grid at the specified position relative to
+    /// the given [ChunkPosition]. When adding, voxels that are mostly determined by the new shape
+    /// take on the given material. Edits with a negative or non-finite smoothness, or a non-finite
+    /// position are ignored.
+    /// Returns true if any stored voxel changed, edits smaller than the precision voxel values are
+    /// stored with don't count as a change
+    pub fn apply_sdf(
+        &mut self,
+        chunk_pos: ChunkPosition,
+        chunk_map: &mut ChunkMap,
+        chunks_getter: impl FnMut(Entity) -> RawChunk,
+        sdf: impl SignedDistanceFunction,
+        mode: Mode,
+        material: u8,
+        smoothness: f32,
+        relative_pos: Vec3,
+    ) -> bool {
+        let voxel_size = *self.voxel_size;
+        self.apply_distance(
+            chunk_pos,
+            chunk_map,
+            chunks_getter,
+            blend_aabb(sdf.aabb(), smoothness, self.smooth_mode),
+            |x, y, z| sdf.sdf(sample_pos_sized(x, y, z, relative_pos, voxel_size)),
+            mode,
+            material,
+            smoothness,
+            relative_pos,
+        )
+    }
+
+    /// Apply a [SignedDistanceFunction] like [ChunkModifier::apply_sdf], but report what the edit
+    /// did instead of silently skipping the voxels it can't apply. Voxels outside of the world
+    /// bounds are clipped and counted in the [EditSummary]. Returns an error if the smoothness,
+    /// position or bounds of the shape are not finite, or if the whole edit lies outside of the
+    /// world
+    pub fn apply_sdf_checked(
+        &mut self,
+        chunk_pos: ChunkPosition,
+        chunk_map: &mut ChunkMap,
+        chunks_getter: impl FnMut(Entity) -> RawChunk,
+        sdf: impl SignedDistanceFunction,
+        mode: Mode,
+        material: u8,
+        smoothness: f32,
+        relative_pos: Vec3,
+    ) -> Result<EditSummary, EditError> {
+        let (aabb_min, aabb_max) = sdf.aabb();
+        if !smoothness.is_finite()
+            || !relative_pos.is_finite()
+            || !aabb_min.is_finite()
+            || !aabb_max.is_finite()
+        {
+            return Err(EditError::NonFiniteInput);
+        }
+        if smoothness < 0. {
+            return Err(EditError::NegativeSmoothness);
+        }
+
+        let voxel_size = *self.voxel_size;
+        let summary = self.apply_distance_summary(
+            chunk_pos,
+            chunk_map,
+            chunks_getter,
+            blend_aabb((aabb_min, aabb_max), smoothness, self.smooth_mode),
+            |x, y, z| sdf.sdf(sample_pos_sized(x, y, z, relative_pos, voxel_size)),
+            mode,
+            material,
+            smoothness,
+            relative_pos,
+        );
+        if summary.touched_chunks == 0 && summary.clipped_voxels > 0 {
+            return Err(EditError::OutOfBounds {
+                clipped_voxels: summary.clipped_voxels,
+            });
+        }
+        Ok(summary)
+    }
+
+    /// Apply a [SignedDistanceFunction] like [ChunkModifier::apply_sdf], but sample the shape on a
+    /// coarse grid first. Blocks of voxels that lie entirely more than [Voxel::MAX_DISTANCE] plus
+    /// the [SmoothMode::reach] from the surface store the same saturated value, so the shape is
+    /// only evaluated per voxel near the surface. This saves most of the evaluations for large
+    /// brushes, while giving the same result as long as the distance of the shape doesn't change
+    /// faster than one meter per meter, like with exact distance functions. Returns true if any
+    /// stored voxel changed
+    pub fn apply_sdf_lod(
+        &mut self,
+        chunk_pos: ChunkPosition,
+        chunk_map: &mut ChunkMap,
+        chunks_getter: impl FnMut(Entity) -> RawChunk,
+        sdf: impl SignedDistanceFunction,
+        mode: Mode,
+        material: u8,
+        smoothness: f32,
+        relative_pos: Vec3,
+    ) -> bool {
+        const BLOCK: i32 = 4;
+        // A margin so rounding can't treat a voxel right at the edge of the band as saturated
+        const MARGIN: f32 = 1e-3;
+        if !relative_pos.is_finite() {
+            return false;
+        }
+
+        // The distance from the center of a block to its corner voxels
+        let voxel_size = *self.voxel_size;
+        let reach = (BLOCK - 1) as f32 / 2. * voxel_size.length();
+        let band = Voxel::MAX_DISTANCE + self.smooth_mode.reach(smoothness);
+        let saturated_distance = saturated_distance(smoothness, self.smooth_mode);
+        let aabb = blend_aabb(sdf.aabb(), smoothness, self.smooth_mode);
+        let (min, max) = voxel_bounds_sized(aabb, relative_pos, voxel_size);
+        let blocks = (max - min + (BLOCK - 1)) / BLOCK;
+        let mut saturated = Vec::with_capacity((blocks.x * blocks.y * blocks.z).max(0) as usize);
+        for bz in 0..blocks.z {
+            for by in 0..blocks.y {
+                for bx in 0..blocks.x {
+                    let first = min + IVec3::new(bx, by, bz) * BLOCK;
+                    let center = (first.as_vec3() + (BLOCK - 1) as f32 / 2. + 1.) * voxel_size
+                        - relative_pos;
+                    let distance = sdf.sdf(center);
+                    // Any distance beyond the band is applied the same way, see apply_voxel
+                    saturated.push(if distance - reach > band + MARGIN {
+                        Some(saturated_distance)
+                    } else if distance + reach < -band - MARGIN {
+                        Some(-saturated_distance)
+                    } else {
+                        None
+                    });
+                }
+            }
+        }
+
+        self.apply_distance(
+            chunk_pos,
+            chunk_map,
+            chunks_getter,
+            aabb,
+            |x, y, z| {
+                let block = (IVec3::new(x, y, z) - min) / BLOCK;
+                let idx = block.x + block.y * blocks.x + block.z * blocks.x * blocks.y;
+                let sample = || sdf.sdf(sample_pos_sized(x, y, z, relative_pos, voxel_size));
+                saturated[idx as usize].unwrap_or_else(sample)
+            },
+            mode,
+            material,
+            smoothness,
+            relative_pos,
+        )
+    }
+
+    /// Apply a [SignedDistanceFunction] like [ChunkModifier::apply_sdf], but with the position
+    /// snapped to the nearest voxel. The shape is then sampled without a fractional offset from the
+    /// voxel grid, so symmetric shapes result in symmetric, grid aligned voxels. Returns true if
+    /// any stored voxel changed
+    pub fn apply_sdf_snapped(
+        &mut self,
+        chunk_pos: ChunkPosition,
+        chunk_map: &mut ChunkMap,
+        chunks_getter: impl FnMut(Entity) -> RawChunk,
+        sdf: impl SignedDistanceFunction,
+        mode: Mode,
+        material: u8,
+        smoothness: f32,
+        relative_pos: Vec3,
+    ) -> bool {
+        let voxel_size = *self.voxel_size;
+        self.apply_sdf(
+            chunk_pos,
+            chunk_map,
+            chunks_getter,
+            sdf,
+            mode,
+            material,
+            smoothness,
+            (relative_pos / voxel_size).round() * voxel_size,
+        )
+    }
+
+    /// Apply a [SignedDistanceFunction] like [ChunkModifier::apply_sdf], but anti-alias the voxels
+    /// at the boundary of the shape. For voxels within one voxel of the surface the distance is
+    /// divided by the length of the gradient of the shape, which gives the distance to the
+    /// sub-voxel surface crossing even for shapes that don't return exact distances, like
+    /// non-uniformly scaled shapes. The value stored for these voxels then places the mesh at the
+    /// fractional crossing, instead of at a distorted one.
+    /// The precision is limited by the 10 bit voxel value, which stores distances in steps of
+    /// `1 / Voxel::THRESHOLD_F32`, or about 2mm. Returns true if any stored voxel changed
+    pub fn apply_sdf_antialiased(
+        &mut self,
+        chunk_pos: ChunkPosition,
+        chunk_map: &mut ChunkMap,
+        chunks_getter: impl FnMut(Entity) -> RawChunk,
+        sdf: impl SignedDistanceFunction,
+        mode: Mode,
+        material: u8,
+        smoothness: f32,
+        relative_pos: Vec3,
+    ) -> bool {
+        // The step used to estimate the gradient with central differences
+        let voxel_size = *self.voxel_size;
+        let step = voxel_size.min_element() / 16.;
+        self.apply_distance(
+            chunk_pos,
+            chunk_map,
+            chunks_getter,
+            blend_aabb(sdf.aabb(), smoothness, self.smooth_mode),
+            |x, y, z| {
+                let pos = sample_pos_sized(x, y, z, relative_pos, voxel_size);
+                let distance = sdf.sdf(pos);
+                if distance.abs() >= voxel_size.max_element() {
+                    return distance;
+                }
+                let difference =
+                    |axis: Vec3| sdf.sdf(pos + axis * step) - sdf.sdf(pos - axis * step);
+                let gradient = Vec3::new(
+                    difference(Vec3::X),
+                    difference(Vec3::Y),
+                    difference(Vec3::Z),
+                ) / (2. * step);
+                let length = gradient.length();
+                if length > f32::EPSILON {
+                    distance / length
+                } else {
+                    distance
+                }
+            },
+            mode,
+            material,
+            smoothness,
+            relative_pos,
+        )
+    }
+
+    /// Remove a [SignedDistanceFunction] from the voxel grid, like [ChunkModifier::apply_sdf] with
+    /// [Mode::Remove]. With `hollow` set only the voxels within that thickness inside the surface
+    /// of the shape are removed, leaving the interior of the shape untouched. Returns true if any
+    /// stored voxel changed
+    pub fn remove_sdf(
+        &mut self,
+        chunk_pos: ChunkPosition,
+        chunk_map: &mut ChunkMap,
+        chunks_getter: impl FnMut(Entity) -> RawChunk,
+        sdf: impl SignedDistanceFunction,
+        hollow: Option<f32>,
+        smoothness: f32,
+        relative_pos: Vec3,
+    ) -> bool {
+        let Some(thickness) = hollow else {
+            return self.apply_sdf(
+                chunk_pos,
+                chunk_map,
+                chunks_getter,
+                sdf,
+                Mode::Remove,
+                0,
+                smoothness,
+                relative_pos,
+            );
+        };
+        if !thickness.is_finite() {
+            return false;
+        }
+
+        let voxel_size = *self.voxel_size;
+        self.apply_distance(
+            chunk_pos,
+            chunk_map,
+            chunks_getter,
+            blend_aabb(sdf.aabb(), smoothness, self.smooth_mode),
+            |x, y, z| {
+                // The band between the surface and `thickness` inside of it
+                let distance = sdf.sdf(sample_pos_sized(x, y, z, relative_pos, voxel_size));
+                distance.max(-distance - thickness)
+            },
+            Mode::Remove,
+            0,
+            smoothness,
+            relative_pos,
+        )
+    }
+
+    /// Apply a sphere to the voxel grid, with the same result as using [ChunkModifier::apply_sdf]
+    /// with a [SphereSdf]. The distance calculation is inlined, which avoids going trough the
+    /// [SignedDistanceFunction] trait for every voxel. Returns true if any stored voxel changed
+    pub fn apply_sphere(
+        &mut self,
+        chunk_pos: ChunkPosition,
+        chunk_map: &mut ChunkMap,
+        chunks_getter: impl FnMut(Entity) -> RawChunk,
+        center: Vec3,
+        radius: f32,
+        mode: Mode,
+        material: u8,
+        smoothness: f32,
+    ) -> bool {
+        let voxel_size = *self.voxel_size;
+        self.apply_distance(
+            chunk_pos,
+            chunk_map,
+            chunks_getter,
+            blend_aabb(
+                (Vec3::splat(-radius), Vec3::splat(radius)),
+                smoothness,
+                self.smooth_mode,
+            ),
+            |x, y, z| sample_pos_sized(x, y, z, center, voxel_size).length() - radius,
+            mode,
+            material,
+            smoothness,
+            center,
+        )
+    }
+
+    /// Apply an [Sdf] like [ChunkModifier::apply_sdf], but reuse the sampled distances from the
+    /// [StampCache] if the same brush was applied before at the same offset from the voxel grid.
+    /// Returns true if any stored voxel changed
+    pub fn apply_stamp(
+        &mut self,
+        cache: &mut StampCache,
+        chunk_pos: ChunkPosition,
+        chunk_map: &mut ChunkMap,
+        chunks_getter: impl FnMut(Entity) -> RawChunk,
+        sdf: &Sdf,
+        mode: Mode,
+        material: u8,
+        smoothness: f32,
+        relative_pos: Vec3,
+    ) -> bool {
+        if !valid_smoothness(smoothness) || !relative_pos.is_finite() {
+            return false;
+        }
+
+        let voxel_size = *self.voxel_size;
+        let aabb = blend_aabb(sdf.aabb(), smoothness, self.smooth_mode);
+        let (min, max) = voxel_bounds_sized(aabb, relative_pos, voxel_size);
+        let size = max - min;
+        let stamp = cache.stamp(sdf, min, max, relative_pos, voxel_size);
+        self.apply_distance(
+            chunk_pos,
+            chunk_map,
+            chunks_getter,
+            aabb,
+            |x, y, z| {
+                let idx = (x - min.x) + (y - min.y) * size.x + (z - min.z) * size.x * size.y;
+                stamp[idx as usize]
+            },
+            mode,
+            material,
+            smoothness,
+            relative_pos,
+        )
+    }
+
+    /// Carve a winding cave tunnel starting at the world-space `start` position, by removing
+    /// [LineSegmentSdf]s along a pseudo-random path of the given length. The path only depends on
+    /// the seed, so carving with the same seed always results in the same cave. Returns true if any
+    /// stored voxel changed
+    pub fn carve_cave(
+        &mut self,
+        start: Vec3,
+        seed: u64,
+        length: f32,
+        radius: f32,
+        chunk_map: &mut ChunkMap,
+        mut chunks_getter: impl FnMut(Entity) -> RawChunk,
+    ) -> bool {
+        // How much the direction of the tunnel can change between segments
+        const TURN: f32 = 0.5;
+        if !length.is_finite() || !radius.is_finite() || radius <= 0. {
+            return false;
+        }
+        let Some((chunk_pos, relative_pos)) = self.voxel_size.relative_position(start) else {
+            return false;
+        };
+
+        let mut random = CaveRandom::new(seed);
+        let mut direction = random.direction();
+        let mut position = Vec3::ZERO;
+        let mut carved = 0.;
+        let mut changed = false;
+        while carved < length {
+            let step = radius.min(length - carved);
+            let end = position + direction * step;
+            changed |= self.apply_sdf(
+                chunk_pos,
+                chunk_map,
+                &mut chunks_getter,
+                LineSegmentSdf {
+                    start: position,
+                    end,
+                    radius,
+                },
+                Mode::Remove,
+                0,
+                0.,
+                relative_pos,
+            );
+            position = end;
+            carved += step;
+            direction = (direction + random.direction() * TURN).normalize_or_zero();
+            if direction == Vec3::ZERO {
+                direction = random.direction();
+            }
+        }
+        changed
+    }
+
+    fn apply_distance(
+        &mut self,
+        chunk_pos: ChunkPosition,
+        chunk_map: &mut ChunkMap,
+        chunks_getter: impl FnMut(Entity) -> RawChunk,
+        aabb: (Vec3, Vec3),
+        distance: impl Fn(i32, i32, i32) -> f32,
+        mode: Mode,
+        material: u8,
+        smoothness: f32,
+        relative_pos: Vec3,
+    ) -> bool {
+        self.apply_distance_summary(
+            chunk_pos,
+            chunk_map,
+            chunks_getter,
+            aabb,
+            distance,
+            mode,
+            material,
+            smoothness,
+            relative_pos,
+        )
+        .changed
+    }
+
+    fn apply_distance_summary(
+        &mut self,
+        chunk_pos: ChunkPosition,
+        chunk_map: &mut ChunkMap,
+        mut chunks_getter: impl FnMut(Entity) -> RawChunk,
+        aabb: (Vec3, Vec3),
+        distance: impl Fn(i32, i32, i32) -> f32,
+        mode: Mode,
+        material: u8,
+        smoothness: f32,
+        relative_pos: Vec3,
+    ) -> EditSummary {
+        let mut summary = EditSummary::default();
+        if !valid_smoothness(smoothness) || !relative_pos.is_finite() {
+            return summary;
+        }
+
+        // Every affected chunk is looked up once, after which its voxels are modified directly
+        const SIZE: i32 = CHUNK_SIDES as i32;
+        let smooth_mode = self.smooth_mode;
+        let (aabb_min, aabb_max) = voxel_bounds_sized(aabb, relative_pos, *self.voxel_size);
+        let (chunks_min, chunks_max) = (
+            aabb_min.div_euclid(IVec3::splat(SIZE)),
+            (aabb_max - 1).div_euclid(IVec3::splat(SIZE)),
+        );
+        for cz in chunks_min.z..=chunks_max.z {
+            for cy in chunks_min.y..=chunks_max.y {
+                for cx in chunks_min.x..=chunks_max.x {
+                    let offset = IVec3::new(cx, cy, cz) * SIZE;
+                    let min = (aabb_min - offset).max(IVec3::ZERO);
+                    let max = (aabb_max - offset).min(IVec3::splat(SIZE));
+                    if min.cmpge(max).any() {
+                        continue;
+                    }
+                    let Some((pos, _)) = locate(chunk_pos, offset.x, offset.y, offset.z) else {
+                        let clipped = max - min;
+                        summary.clipped_voxels += (clipped.x * clipped.y * clipped.z) as usize;
+                        continue;
+                    };
+                    summary.touched_chunks += 1;
+
+                    let chunk = self.get_chunk(pos, chunk_map, &mut chunks_getter);
+                    let mut chunk_changed = false;
+                    for z in min.z..max.z {
+                        for y in min.y..max.y {
+                            for x in min.x..max.x {
+                                let voxel = chunk.get_mut_voxel(x as u32, y as u32, z as u32);
+                                let distance = distance(x + offset.x, y + offset.y, z + offset.z);
+                                chunk_changed |= apply_voxel(
+                                    voxel,
+                                    distance,
+                                    mode,
+                                    material,
+                                    smoothness,
+                                    smooth_mode,
+                                );
+                            }
+                        }
+                    }
+                    if chunk_changed {
+                        self.modified.get_mut(&pos).unwrap().changed = true;
+                        summary.changed = true;
+                    }
+                }
+            }
+        }
+        summary
+    }
+
+    /// Calculate how many voxels would become solid if the [SignedDistanceFunction] was applied
+    /// with [ChunkModifier::apply_sdf], minus the number of voxels that would stop being solid.
+    /// Nothing is modified, so this can be used to preview an edit before it is made
+    pub fn preview_sdf(
+        &self,
+        chunk_pos: ChunkPosition,
+        chunk_map: &ChunkMap,
+        mut chunks_getter: impl FnMut(Entity) -> RawChunk,
+        sdf: impl SignedDistanceFunction,
+        mode: Mode,
+        smoothness: f32,
+        relative_pos: Vec3,
+    ) -> i32 {
+        if !valid_smoothness(smoothness) || !relative_pos.is_finite() {
+            return 0;
+        }
+
+        let mut loaded = HashMap::<ChunkPosition, RawChunk>::default();
+        let mut change = 0;
+        let voxel_size = *self.voxel_size;
+        let aabb = blend_aabb(sdf.aabb(), smoothness, self.smooth_mode);
+        let (aabb_min, aabb_max) = voxel_bounds_sized(aabb, relative_pos, voxel_size);
+        for x in aabb_min.x..aabb_max.x {
+            for y in aabb_min.y..aabb_max.y {
+                for z in aabb_min.z..aabb_max.z {
+                    let Some((pos, [vx, vy, vz])) = locate(chunk_pos, x, y, z) else {
+                        continue;
+                    };
+                    let voxel = match (self.modified.get(&pos), chunk_map.get(&pos)) {
+                        (Some(chunk), _) => self.chunks[chunk.index].get_voxel(vx, vy, vz),
+                        (None, Some(entity)) => loaded
+                            .entry(pos)
+                            .or_insert_with(|| chunks_getter(*entity))
+                            .get_voxel(vx, vy, vz),
+                        (None, None) => Voxel::AIR,
+                    };
+                    let cur_value = f32::from(voxel);
+                    let distance = sdf.sdf(sample_pos_sized(x, y, z, relative_pos, voxel_size));
+                    let value = blend(
+                        mode,
+                        cur_value,
+                        clamp_distance(distance, smoothness, self.smooth_mode),
+                        smoothness,
+                        self.smooth_mode,
+                    );
+                    match (
+                        voxel.is_solid(),
+                        voxel.with_value_f32(value.clamp(-1., 1.)).is_solid(),
+                    ) {
+                        (false, true) => change += 1,
+                        (true, false) => change -= 1,
+                        _ => {}
+                    }
+                }
+            }
+        }
+        change
+    }
+}
+
+/// What an edit applied with [ChunkModifier::apply_sdf_checked] did
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct EditSummary {
+    /// The number of chunks the edit was applied to
+    pub touched_chunks: usize,
+    /// The number of voxels that were skipped because they lie outside of the world bounds
+    pub clipped_voxels: usize,
+    /// Whether any stored voxel changed
+    pub changed: bool,
+}
+
+/// The error returned when an edit can't be applied, see [ChunkModifier::apply_sdf_checked]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EditError {
+    /// Every voxel the edit could affect lies outside of the world bounds
+    OutOfBounds {
+        /// The number of voxels that were skipped
+        clipped_voxels: usize,
+    },
+    /// The smoothness, position or bounds of the edit are not finite
+    NonFiniteInput,
+    /// The smoothness of the edit is negative
+    NegativeSmoothness,
+}
+
+impl std::fmt::Display for EditError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EditError::OutOfBounds { clipped_voxels } => write!(
+                f,
+                "edit lies outside of the world bounds, {clipped_voxels} voxels were clipped"
+            ),
+            EditError::NonFiniteInput => write!(f, "edit has a non-finite position or size"),
+            EditError::NegativeSmoothness => write!(f, "edit has a negative smoothness"),
+        }
+    }
+}
+
+impl std::error::Error for EditError {}
+
+/// Smooth the terrain of a chunk by repeatedly blurring the signed distance values of its voxels.
+/// The neighboring chunks are loaded as well, so voxels at the edge of the chunk are blurred with
+/// the voxels across the border, and the layer of voxels bordering the chunk is smoothed along
+/// with it to avoid seams. Returns every chunk with voxels that changed
+pub fn smooth_region(
+    center_chunk: ChunkPosition,
+    chunk_map: &ChunkMap,
+    mut chunks_getter: impl FnMut(Entity) -> RawChunk,
+    iterations: usize,
+) -> Vec<(ChunkPosition, RawChunk)> {
+    const SIZE: usize = CHUNK_SIDES;
+    const REGION: usize = SIZE * 3;
+    let idx = |x: usize, y: usize, z: usize| x + y * REGION + z * REGION * REGION;
+
+    let mut chunks = Vec::with_capacity(FETCH_SHAPE.usize());
+    for i in 0..FETCH_SHAPE.usize() {
+        let [x, y, z] = FETCH_SHAPE.delinearize(i as u8);
+        let pos = IVec3::new(
+            center_chunk[0] as i32 + x as i32 - 1,
+            center_chunk[1] as i32 + y as i32 - 1,
+            center_chunk[2] as i32 + z as i32 - 1,
+        );
+        let pos = ChunkPosition::is_valid(pos.x, pos.y, pos.z)
+            .then(|| ChunkPosition::new(pos.x as i8, pos.y as i8, pos.z as i8));
+        let chunk = match pos.and_then(|pos| chunk_map.get(&pos)) {
+            Some(entity) => chunks_getter(*entity),
+            None => RawChunk::air(),
+        };
+        chunks.push((pos, chunk));
+    }
+
+    let mut values = vec![0.; REGION * REGION * REGION];
+    for (i, (_, chunk)) in chunks.iter().enumerate() {
+        let [cx, cy, cz] = FETCH_SHAPE.delinearize(i as u8).map(|v| v as usize * SIZE);
+        for z in 0..SIZE {
+            for y in 0..SIZE {
+                for x in 0..SIZE {
+                    values[idx(cx + x, cy + y, cz + z)] =
+                        f32::from(chunk.get_voxel(x as u32, y as u32, z as u32));
+                }
+            }
+        }
+    }
+
+    // Only the center chunk and the layer of voxels around it get blurred
+    let smoothed = SIZE - 1..2 * SIZE + 1;
+    let mut blurred = values.clone();
+    for _ in 0..iterations {
+        for z in smoothed.clone() {
+            for y in smoothed.clone() {
+                for x in smoothed.clone() {
+                    let mut sum = 0.;
+                    for dz in z - 1..=z + 1 {
+                        for dy in y - 1..=y + 1 {
+                            for dx in x - 1..=x + 1 {
+                                sum += values[idx(dx, dy, dz)];
+                            }
+                        }
+                    }
+                    blurred[idx(x, y, z)] = sum / 27.;
+                }
+            }
+        }
+        values.copy_from_slice(&blurred);
+    }
+
+    let mut changed = Vec::new();
+    for (i, (pos, mut chunk)) in chunks.into_iter().enumerate() {
+        let Some(pos) = pos else {
+            continue;
+        };
+        let [cx, cy, cz] = FETCH_SHAPE.delinearize(i as u8).map(|v| v as usize * SIZE);
+        let mut modified = false;
+        for z in 0..SIZE {
+            for y in 0..SIZE {
+                for x in 0..SIZE {
+                    let (rx, ry, rz) = (cx + x, cy + y, cz + z);
+                    if !smoothed.contains(&rx) || !smoothed.contains(&ry) || !smoothed.contains(&rz)
+                    {
+                        continue;
+                    }
+                    let voxel = chunk.get_mut_voxel(x as u32, y as u32, z as u32);
+                    let new = voxel.with_value_f32(values[idx(rx, ry, rz)]);
+                    modified |= new != *voxel;
+                    *voxel = new;
+                }
+            }
+        }
+        if modified {
+            changed.push((pos, chunk));
+        }
+    }
+    changed
+}
+
+/// A cache of sampled [Sdf]s, used by [ChunkModifier::apply_stamp]. Brushes are identified by their
+/// shape, their dimensions and their offset from the voxel grid, rounded to [STAMP_PRECISION].
+/// The smoothness is applied when the stamp is blended, so brushes with a different smoothness
+/// share the same stamp as long as their [SmoothMode::reach] covers the same voxels
+#[derive(Default)]
+pub struct StampCache(HashMap<StampKey, Stamp>);
+
+/// The precision, in meters, at which brushes are considered identical by the [StampCache]
+pub const STAMP_PRECISION: f32 = 0.001;
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct StampKey {
+    shape: Vec<i32>,
+    offset: [i32; 3],
+    voxel_size: [i32; 3],
+}
+
+/// A small splitmix64 generator for [ChunkModifier::carve_cave], so caves are reproducible from
+/// their seed without extra dependencies
+struct CaveRandom(u64);
+
+impl CaveRandom {
+    fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Get a random float in the -1..1 range
+    fn signed(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 23) as f32 - 1.
+    }
+
+    /// Get a random direction, which is never zero
+    fn direction(&mut self) -> Vec3 {
+        loop {
+            let v = Vec3::new(self.signed(), self.signed(), self.signed());
+            if v.length_squared() > 0.01 {
+                return v.normalize();
+            }
+        }
+    }
+}
+
+/// Describe the shape and dimensions of an sdf, rounded to [STAMP_PRECISION]
+fn describe_sdf(sdf: &Sdf, shape: &mut Vec<i32>) {
+    let quantize = |v: f32| (v / STAMP_PRECISION).round() as i32;
+    match sdf {
+        Sdf::Sphere(sphere) => shape.extend([0, quantize(sphere.0)]),
+        Sdf::Box(b) => {
+            shape.push(1);
+            shape.extend(b.0.to_array().map(quantize));
+        }
+        Sdf::Cylinder(cylinder) => shape.extend([
+            2,
+            quantize(cylinder.radius),
+            quantize(cylinder.height),
+            cylinder.axis as i32,
+        ]),
+        Sdf::Shell(shell) => shape.extend([8, quantize(shell.radius), quantize(shell.thickness)]),
+        Sdf::LineSegment(segment) => {
+            shape.push(9);
+            shape.extend(segment.start.to_array().map(quantize));
+            shape.extend(segment.end.to_array().map(quantize));
+            shape.push(quantize(segment.radius));
+        }
+        Sdf::Scaled(scaled) => {
+            shape.push(3);
+            shape.extend(scaled.scale.to_array().map(quantize));
+            describe_sdf(&scaled.inner, shape);
+        }
+        Sdf::Rotated(rotated) => {
+            shape.push(4);
+            shape.extend(rotated.rotation.to_array().map(quantize));
+            describe_sdf(&rotated.inner, shape);
+        }
+        Sdf::Translated(translated) => {
+            shape.push(5);
+            shape.extend(translated.offset.to_array().map(quantize));
+            describe_sdf(&translated.inner, shape);
+        }
+        Sdf::Union(union) => {
+            shape.push(6);
+            describe_sdf(&union.0, shape);
+            describe_sdf(&union.1, shape);
+        }
+        Sdf::Subtract(subtract) => {
+            shape.push(7);
+            describe_sdf(&subtract.0, shape);
+            describe_sdf(&subtract.1, shape);
+        }
+    }
+}
+
+struct Stamp {
+    size: IVec3,
+    distances: Vec<f32>,
+}
+
+impl StampCache {
+    /// Get the sampled distances for the sdf covering the voxels from min to max, in the same
+    /// order as voxels are stored in a chunk
+    fn stamp(
+        &mut self,
+        sdf: &Sdf,
+        min: IVec3,
+        max: IVec3,
+        relative_pos: Vec3,
+        voxel_size: Vec3,
+    ) -> &[f32] {
+        let quantize = |v: f32| (v / STAMP_PRECISION).round() as i32;
+        let mut shape = Vec::new();
+        describe_sdf(sdf, &mut shape);
+        let key = StampKey {
+            shape,
+            offset: sample_pos_sized(min.x, min.y, min.z, relative_pos, voxel_size)
+                .to_array()
+                .map(quantize),
+            voxel_size: voxel_size.to_array().map(quantize),
         };
 
-        Some(chunk_data.get_mut_voxel(relative_x as u32, relative_y as u32, relative_z as u32))
+        let size = max - min;
+        let stamp = self.0.entry(key).or_insert_with(|| Stamp {
+            size: IVec3::ZERO,
+            distances: Vec::new(),
+        });
+        if stamp.size != size {
+            stamp.size = size;
+            stamp.distances.clear();
+            for z in min.z..max.z {
+                for y in min.y..max.y {
+                    for x in min.x..max.x {
+                        stamp.distances.push(sdf.sdf(sample_pos_sized(
+                            x,
+                            y,
+                            z,
+                            relative_pos,
+                            voxel_size,
+                        )));
+                    }
+                }
+            }
+        }
+        &stamp.distances
+    }
+
+    /// The number of stamps in the cache
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Check if the cache is empty
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Remove all stamps from the cache
+    pub fn clear(&mut self) {
+        self.0.clear();
+    }
+}
+
+/// Split a voxel position relative to a chunk into the chunk that holds the voxel and the position
+/// of the voxel within that chunk. Returns None if the voxel lies outside of the world
+fn locate(
+    chunk_pos: ChunkPosition,
+    relative_x: i32,
+    relative_y: i32,
+    relative_z: i32,
+) -> Option<(ChunkPosition, [u32; 3])> {
+    const SIZE: i32 = CHUNK_SIDES as i32;
+    let mut chunk_pos = IVec3::new(
+        chunk_pos[0] as i32,
+        chunk_pos[1] as i32,
+        chunk_pos[2] as i32,
+    );
+    let orig_pos = chunk_pos;
+    chunk_pos += IVec3::new(
+        (relative_x as f32 / SIZE as f32).floor() as i32,
+        (relative_y as f32 / SIZE as f32).floor() as i32,
+        (relative_z as f32 / SIZE as f32).floor() as i32,
+    );
+
+    if !ChunkPosition::is_valid(chunk_pos.x, chunk_pos.y, chunk_pos.z) {
+        return None;
+    }
+
+    let offset = orig_pos - chunk_pos;
+    let chunk_pos = ChunkPosition::new(chunk_pos.x as i8, chunk_pos.y as i8, chunk_pos.z as i8);
+    let relative_x = relative_x + offset.x * SIZE;
+    let relative_y = relative_y + offset.y * SIZE;
+    let relative_z = relative_z + offset.z * SIZE;
+
+    Some((
+        chunk_pos,
+        [relative_x as u32, relative_y as u32, relative_z as u32],
+    ))
+}
+
+/// Get the range of voxels, relative to the chunk, that a shape with the given bounding box placed
+/// at relative_pos can affect. The maximum is exclusive.
+/// Voxels up to [Voxel::MAX_DISTANCE] outside of the shape get a value as well, and voxel x is
+/// sampled at `(x + 1) * VOXEL_SIZE`, see [sample_pos]. Both sides of the range are derived the
+/// same way, with a small margin so rounding can't exclude a voxel at the edge of the range
+pub(crate) fn voxel_bounds(aabb: (Vec3, Vec3), relative_pos: Vec3) -> (IVec3, IVec3) {
+    voxel_bounds_sized(aabb, relative_pos, Vec3::splat(VOXEL_SIZE))
+}
+
+/// Get the range of voxels a shape can affect like [voxel_bounds], for voxels of the given size
+pub(crate) fn voxel_bounds_sized(
+    (aabb_min, aabb_max): (Vec3, Vec3),
+    relative_pos: Vec3,
+    voxel_size: Vec3,
+) -> (IVec3, IVec3) {
+    const MARGIN: f32 = 1e-3;
+    let first = (aabb_min - Voxel::MAX_DISTANCE + relative_pos) / voxel_size - 1.;
+    let first = (first - MARGIN).ceil();
+    let last = (aabb_max + Voxel::MAX_DISTANCE + relative_pos) / voxel_size - 1.;
+    let last = (last + MARGIN).floor();
+
+    (
+        IVec3::new(first.x as i32, first.y as i32, first.z as i32),
+        IVec3::new(last.x as i32, last.y as i32, last.z as i32) + 1,
+    )
+}
+
+/// Get the position to sample the sdf at for the voxel at the specified relative coordinates
+#[inline(always)]
+pub(crate) fn sample_pos(x: i32, y: i32, z: i32, relative_pos: Vec3) -> Vec3 {
+    sample_pos_sized(x, y, z, relative_pos, Vec3::splat(VOXEL_SIZE))
+}
+
+/// Get the position to sample the sdf at like [sample_pos], for voxels of the given size
+#[inline(always)]
+pub(crate) fn sample_pos_sized(
+    x: i32,
+    y: i32,
+    z: i32,
+    relative_pos: Vec3,
+    voxel_size: Vec3,
+) -> Vec3 {
+    // TODO: Figure out a cleaner solution than this offset
+    (IVec3::new(x, y, z) + 1).as_vec3() * voxel_size - relative_pos
+}
+
+/// Modify a single voxel with the distance to a shape, returning true if the stored voxel changed.
+/// The quantized voxels are compared, so changes smaller than a single value step are no-ops
+#[inline(always)]
+pub(crate) fn apply_voxel(
+    voxel: &mut Voxel,
+    distance: f32,
+    mode: Mode,
+    material: u8,
+    smoothness: f32,
+    smooth_mode: SmoothMode,
+) -> bool {
+    let before = *voxel;
+    let cur_value = f32::from(*voxel);
+    if let Mode::Clear = mode {
+        if distance < 0. {
+            *voxel = Voxel::AIR;
+        }
+        return *voxel != before;
+    }
+    if let Mode::Add = mode {
+        let saturated = saturated_distance(smoothness, smooth_mode);
+        if takes_material(cur_value, distance.clamp(-saturated, saturated)) {
+            *voxel = Voxel::new(material, voxel.value());
+        }
+    }
+    let value = blend(
+        mode,
+        cur_value,
+        clamp_distance(distance, smoothness, smooth_mode),
+        smoothness,
+        smooth_mode,
+    );
+    *voxel = voxel.with_value_f32(value.clamp(-1., 1.));
+    *voxel != before
+}
+
+/// Clamp the distance to a shape before it is blended with a voxel. Distances outside of the shape
+/// are kept up to [SmoothMode::reach] beyond [Voxel::MAX_DISTANCE], so the blend fades out over
+/// the smoothness instead of every voxel within the bounds of the shape being pulled towards it
+#[inline(always)]
+fn clamp_distance(distance: f32, smoothness: f32, smooth_mode: SmoothMode) -> f32 {
+    distance.clamp(-1., Voxel::MAX_DISTANCE + smooth_mode.reach(smoothness))
+}
+
+/// Get the distance beyond which every distance to a shape is applied the same way, both for the
+/// blended value and for picking the material, so [ChunkModifier::apply_sdf_lod] can substitute it
+/// for voxels far from the surface. Twice the band lies beyond every stored value, so clamping to
+/// it doesn't change which voxels take the material either
+#[inline(always)]
+fn saturated_distance(smoothness: f32, smooth_mode: SmoothMode) -> f32 {
+    2. * (Voxel::MAX_DISTANCE + smooth_mode.reach(smoothness))
+}
+
+/// Check that edits can be blended with the smoothness, meaning it is finite and not negative
+#[inline(always)]
+pub(crate) fn valid_smoothness(smoothness: f32) -> bool {
+    smoothness.is_finite() && smoothness >= 0.
+}
+
+/// Grow the bounds of a shape by how far smoothing can reach beyond its surface, see
+/// [SmoothMode::reach]
+pub(crate) fn blend_aabb(
+    (aabb_min, aabb_max): (Vec3, Vec3),
+    smoothness: f32,
+    smooth_mode: SmoothMode,
+) -> (Vec3, Vec3) {
+    let reach = smooth_mode.reach(smoothness);
+    (aabb_min - reach, aabb_max + reach)
+}
+
+/// Combine the current value of a voxel with the clamped sdf value of a shape
+#[inline(always)]
+fn blend(
+    mode: Mode,
+    cur_value: f32,
+    new_value: f32,
+    smoothness: f32,
+    smooth_mode: SmoothMode,
+) -> f32 {
+    // Without smoothing plain min and max are used, the smooth variants would divide by zero
+    let hard = smoothness == 0.;
+    match mode {
+        Mode::Add if hard => cur_value.min(new_value),
+        Mode::Add => smooth_mode.smin(cur_value, new_value, smoothness),
+        Mode::Remove if hard => cur_value.max(-new_value),
+        Mode::Remove => smooth_mode.smax(cur_value, -new_value, smoothness),
+        Mode::Clear if new_value < 0. => 1.,
+        Mode::Clear => cur_value,
+    }
+}
+
+/// Check if a voxel should take the material of an added shape, which is the case when the shape
+/// is closer than the existing surface. This is where the new shape makes up most of the smooth
+/// blend, so the material changes halfway trough the blended region for every smoothness. The
+/// unclamped distance is used so voxels deep inside the new shape still take its material when the
+/// stored value is already solid
+#[inline(always)]
+fn takes_material(cur_value: f32, distance: f32) -> bool {
+    distance < cur_value
+}
+
+/// The function used to smoothly blend a shape with the existing terrain, the forms are taken from
+/// <https://iquilezles.org/articles/smin>. The smoothness is the distance over which the shapes
+/// are blended
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SmoothMode {
+    /// Quadratic polynomial smin, two surfaces at the same distance are blended `smoothness / 4`
+    /// beyond their minimum
+    #[default]
+    Polynomial,
+    /// Exponential smin, which gives the smoothest blend but also slightly affects surfaces that
+    /// are further apart than the smoothness. Two surfaces at the same distance are blended
+    /// `smoothness` beyond their minimum
+    Exponential,
+    /// Cubic polynomial smin, which has a smoother transition than [SmoothMode::Polynomial]. Two
+    /// surfaces at the same distance are blended `smoothness / 6` beyond their minimum
+    Cubic,
+}
+
+impl SmoothMode {
+    /// Smoothly blend the minimum of a and b
+    #[inline(always)]
+    pub fn smin(self, a: f32, b: f32, k: f32) -> f32 {
+        match self {
+            SmoothMode::Polynomial => {
+                let h = (k - (a - b).abs()).max(0.0);
+                a.min(b) - h * h * 0.25 / k
+            }
+            SmoothMode::Exponential => {
+                // Shifted by the minimum so the exponents can't overflow for small k
+                let m = a.min(b);
+                m - k * ((-(a - m) / k).exp2() + (-(b - m) / k).exp2()).log2()
+            }
+            SmoothMode::Cubic => {
+                let h = (k - (a - b).abs()).max(0.0) / k;
+                a.min(b) - h * h * h * k / 6.
+            }
+        }
+    }
+
+    /// Smoothly blend the maximum of a and b
+    #[inline(always)]
+    pub fn smax(self, a: f32, b: f32, k: f32) -> f32 {
+        -self.smin(-a, -b, k)
+    }
+
+    /// Get how much further apart than [Voxel::MAX_DISTANCE] a voxel and a shape can be while
+    /// blending them still changes the voxel. The exponential form never fully stops blending, so
+    /// its reach is where the change drops below a single step of the stored voxel values
+    pub fn reach(self, k: f32) -> f32 {
+        let k = k.max(0.);
+        match self {
+            SmoothMode::Polynomial | SmoothMode::Cubic => k,
+            SmoothMode::Exponential => {
+                // Two values d apart are blended by at most k * 2^(-d / k) / ln(2)
+                let step = 2. / Voxel::MAX_VALUE as f32;
+                (k * (k / (step * std::f32::consts::LN_2)).log2()).max(0.)
+            }
+        }
+    }
+}
+
+#[test]
+fn test_smooth_modes() {
+    assert_eq!(-0.25, SmoothMode::Polynomial.smin(0., 0., 1.));
+    assert_eq!(-1., SmoothMode::Exponential.smin(0., 0., 1.));
+    assert!((SmoothMode::Cubic.smin(0., 0., 1.) + 1. / 6.).abs() < 1e-6);
+    for mode in [
+        SmoothMode::Polynomial,
+        SmoothMode::Exponential,
+        SmoothMode::Cubic,
+    ] {
+        assert_eq!(-mode.smin(0., 0., 1.), mode.smax(0., 0., 1.));
+    }
+    // The polynomial forms don't affect surfaces further apart than the smoothness
+    assert_eq!(1., SmoothMode::Polynomial.smin(1., 3., 1.));
+    assert_eq!(1., SmoothMode::Cubic.smin(1., 3., 1.));
+    assert!(SmoothMode::Exponential.smin(1., 3., 1.) < 1.);
+}
+
+#[test]
+fn test_smooth_reach() {
+    let step = 2. / Voxel::MAX_VALUE as f32;
+    for mode in [
+        SmoothMode::Polynomial,
+        SmoothMode::Exponential,
+        SmoothMode::Cubic,
+    ] {
+        assert_eq!(0., mode.reach(0.));
+        assert_eq!(0., mode.reach(-1.));
+        for k in [0.01, 0.3, 1., 4.] {
+            // Air next to a shape just beyond the reach doesn't change, closer shapes do
+            let reach = mode.reach(k);
+            assert!((1. - mode.smin(1., 1. + reach, k)).abs() < step);
+            assert!(1. - mode.smin(1., 1. + reach * 0.5, k) > 0.);
+        }
+    }
+    // The exponential tail is cut off where it stops mattering, not at a multiple of k
+    let reach = SmoothMode::Exponential.reach(4.);
+    assert!(reach > 4. && reach < 48., "{reach}");
+    assert_eq!(0., SmoothMode::Exponential.reach(1e-4));
+}
+
+/// A trait for a signed distance function
+#[enum_dispatch]
+pub trait SignedDistanceFunction {
+    /// Get the sdf value at the provided position, with the shape at 0,0,0
+    fn sdf(&self, pos: Vec3) -> f32;
+    /// Get the bounding box for the shape
+    fn aabb(&self) -> (Vec3, Vec3);
+}
+
+impl<T: SignedDistanceFunction> SignedDistanceFunction for &T {
+    fn sdf(&self, pos: Vec3) -> f32 {
+        (*self).sdf(pos)
+    }
+
+    fn aabb(&self) -> (Vec3, Vec3) {
+        (*self).aabb()
+    }
+}
+
+/// An enum with SDF variants, used to pass the SignedDistanceFunction trait around without Box or
+/// dynamic dispatch
+#[derive(Debug, Serialize, Deserialize)]
+#[enum_dispatch(SignedDistanceFunction)]
+pub enum Sdf {
+    /// A sphere
+    Sphere(SphereSdf),
+    /// A box
+    Box(BoxSdf),
+    /// A cylinder aligned with one of the axes
+    Cylinder(CylinderSdf),
+    /// A hollow sphere
+    Shell(ShellSdf),
+    /// A line segment with rounded ends, like a capsule
+    LineSegment(LineSegmentSdf),
+    /// A scaled sdf
+    Scaled(ScaledSdf),
+    /// A rotated sdf
+    Rotated(RotatedSdf),
+    /// A translated sdf
+    Translated(TranslatedSdf),
+    /// The union of two sdfs
+    Union(UnionSdf),
+    /// An sdf with another sdf cut out of it
+    Subtract(SubtractSdf),
+}
+
+impl Sdf {
+    /// Scale the shape along each axis
+    pub fn scaled(self, scale: Vec3) -> Sdf {
+        ScaledSdf {
+            inner: Box::new(self),
+            scale,
+        }
+        .into()
+    }
+
+    /// Rotate the shape around its origin
+    pub fn rotated(self, rotation: Quat) -> Sdf {
+        RotatedSdf {
+            inner: Box::new(self),
+            rotation,
+        }
+        .into()
+    }
+
+    /// Move the shape away from its origin
+    pub fn translated(self, offset: Vec3) -> Sdf {
+        TranslatedSdf {
+            inner: Box::new(self),
+            offset,
+        }
+        .into()
+    }
+
+    /// Combine the shape with another shape
+    pub fn union(self, other: Sdf) -> Sdf {
+        UnionSdf(Box::new(self), Box::new(other)).into()
+    }
+
+    /// Cut another shape out of the shape
+    pub fn subtract(self, other: Sdf) -> Sdf {
+        SubtractSdf(Box::new(self), Box::new(other)).into()
+    }
+
+    /// Check if applying this sdf at relative_pos, relative to the origin chunk, can modify any
+    /// voxels in the given chunk. This uses the same bounds as [ChunkModifier::apply_sdf] without
+    /// smoothing, smoothed edits can reach [SmoothMode::reach] further
+    pub fn touches_chunk(
+        &self,
+        relative_pos: Vec3,
+        chunk: ChunkPosition,
+        origin: ChunkPosition,
+    ) -> bool {
+        const SIZE: i32 = CHUNK_SIDES as i32;
+        let (aabb_min, aabb_max) = voxel_bounds(self.aabb(), relative_pos);
+        let chunk_min = IVec3::new(
+            (chunk[0] as i32 - origin[0] as i32) * SIZE,
+            (chunk[1] as i32 - origin[1] as i32) * SIZE,
+            (chunk[2] as i32 - origin[2] as i32) * SIZE,
+        );
+        let chunk_max = chunk_min + SIZE;
+        aabb_min.cmplt(chunk_max).all() && aabb_max.cmpgt(chunk_min).all()
+    }
+}
+
+/// A signed distance sphere
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SphereSdf(pub f32);
+
+impl SignedDistanceFunction for SphereSdf {
+    fn sdf(&self, pos: Vec3) -> f32 {
+        pos.length() - self.0
+    }
+
+    fn aabb(&self) -> (Vec3, Vec3) {
+        (Vec3::splat(-self.0), Vec3::splat(self.0))
+    }
+}
+
+/// A signed distance box
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BoxSdf(pub Vec3);
+
+impl SignedDistanceFunction for BoxSdf {
+    fn sdf(&self, pos: Vec3) -> f32 {
+        let q = pos.abs() - self.0;
+        q.max(Vec3::ZERO).length() + q.x.max(q.y).max(q.z).min(0.0)
+    }
+
+    fn aabb(&self) -> (Vec3, Vec3) {
+        (-self.0, self.0)
+    }
+}
+
+/// A signed distance hollow sphere, like a dome or a bubble. The shell is centered on the radius
+/// and extends `thickness` inward and outward
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ShellSdf {
+    /// The radius of the middle of the shell
+    pub radius: f32,
+    /// The distance the shell extends from its radius, half of the total thickness
+    pub thickness: f32,
+}
+
+impl SignedDistanceFunction for ShellSdf {
+    fn sdf(&self, pos: Vec3) -> f32 {
+        (pos.length() - self.radius).abs() - self.thickness
+    }
+
+    fn aabb(&self) -> (Vec3, Vec3) {
+        let extent = Vec3::splat(self.radius + self.thickness);
+        (-extent, extent)
+    }
+}
+
+/// A signed distance line segment with a radius, a capsule between two points. Unlike the other
+/// shapes it isn't centered on its origin, so a path can be built from segments that share points
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LineSegmentSdf {
+    /// The point the segment starts at
+    pub start: Vec3,
+    /// The point the segment ends at
+    pub end: Vec3,
+    /// The distance the shape extends from the segment
+    pub radius: f32,
+}
+
+impl SignedDistanceFunction for LineSegmentSdf {
+    fn sdf(&self, pos: Vec3) -> f32 {
+        let (pa, ba) = (pos - self.start, self.end - self.start);
+        let h = if ba == Vec3::ZERO {
+            0.
+        } else {
+            (pa.dot(ba) / ba.length_squared()).clamp(0., 1.)
+        };
+        (pa - ba * h).length() - self.radius
+    }
+
+    fn aabb(&self) -> (Vec3, Vec3) {
+        (
+            self.start.min(self.end) - self.radius,
+            self.start.max(self.end) + self.radius,
+        )
+    }
+}
+
+/// An axis in 3D space
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Axis {
+    /// The X axis
+    X,
+    /// The Y axis, which points up
+    #[default]
+    Y,
+    /// The Z axis
+    Z,
+}
+
+/// A signed distance cylinder
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CylinderSdf {
+    /// The radius of the cylinder
+    pub radius: f32,
+    /// The height of the cylinder, measured from its center to either cap
+    pub height: f32,
+    /// The axis the cylinder is aligned with, [Axis::Y] for a vertical cylinder
+    #[serde(default)]
+    pub axis: Axis,
+}
+
+impl CylinderSdf {
+    /// Create a vertical cylinder, aligned with [Axis::Y]
+    pub fn new(radius: f32, height: f32) -> Self {
+        Self {
+            radius,
+            height,
+            axis: Axis::Y,
+        }
+    }
+
+    /// Split the position into the offset perpendicular to the axis and the offset along it
+    fn split(&self, pos: Vec3) -> (Vec2, f32) {
+        match self.axis {
+            Axis::X => (pos.yz(), pos.x),
+            Axis::Y => (pos.xz(), pos.y),
+            Axis::Z => (pos.xy(), pos.z),
+        }
+    }
+}
+
+impl SignedDistanceFunction for CylinderSdf {
+    fn sdf(&self, pos: Vec3) -> f32 {
+        let (radial, along) = self.split(pos);
+        let d = Vec2::new(radial.length(), along).abs() - Vec2::new(self.radius, self.height);
+        d.x.max(d.y).min(0.0) + d.max(Vec2::ZERO).length()
+    }
+
+    fn aabb(&self) -> (Vec3, Vec3) {
+        let half_extents = match self.axis {
+            Axis::X => Vec3::new(self.height, self.radius, self.radius),
+            Axis::Y => Vec3::new(self.radius, self.height, self.radius),
+            Axis::Z => Vec3::new(self.radius, self.radius, self.height),
+        };
+        (-half_extents, half_extents)
+    }
+}
+
+/// Get the bounding box around a bounding box with every corner transformed
+fn transform_aabb((min, max): (Vec3, Vec3), transform: impl Fn(Vec3) -> Vec3) -> (Vec3, Vec3) {
+    (0..8)
+        .map(|i| {
+            transform(Vec3::select(
+                BVec3::new(i & 1 != 0, i & 2 != 0, i & 4 != 0),
+                max,
+                min,
+            ))
+        })
+        .fold(
+            (Vec3::INFINITY, Vec3::NEG_INFINITY),
+            |(min, max), corner| (min.min(corner), max.max(corner)),
+        )
+}
+
+/// A signed distance function scaled along each axis. Non-uniform scaling distorts distances,
+/// the result is scaled by the smallest factor so it never overestimates the distance
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ScaledSdf {
+    /// The sdf to scale
+    pub inner: Box<Sdf>,
+    /// The scale along each axis
+    pub scale: Vec3,
+}
+
+impl SignedDistanceFunction for ScaledSdf {
+    fn sdf(&self, pos: Vec3) -> f32 {
+        self.inner.sdf(pos / self.scale) * self.scale.abs().min_element()
+    }
+
+    fn aabb(&self) -> (Vec3, Vec3) {
+        transform_aabb(self.inner.aabb(), |corner| corner * self.scale)
+    }
+}
+
+/// A signed distance function rotated around its origin
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RotatedSdf {
+    /// The sdf to rotate
+    pub inner: Box<Sdf>,
+    /// The rotation
+    pub rotation: Quat,
+}
+
+impl SignedDistanceFunction for RotatedSdf {
+    fn sdf(&self, pos: Vec3) -> f32 {
+        self.inner.sdf(self.rotation.inverse() * pos)
+    }
+
+    fn aabb(&self) -> (Vec3, Vec3) {
+        transform_aabb(self.inner.aabb(), |corner| self.rotation * corner)
+    }
+}
+
+/// A signed distance function moved away from its origin
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TranslatedSdf {
+    /// The sdf to move
+    pub inner: Box<Sdf>,
+    /// The offset from the origin
+    pub offset: Vec3,
+}
+
+impl SignedDistanceFunction for TranslatedSdf {
+    fn sdf(&self, pos: Vec3) -> f32 {
+        self.inner.sdf(pos - self.offset)
+    }
+
+    fn aabb(&self) -> (Vec3, Vec3) {
+        let (min, max) = self.inner.aabb();
+        (min + self.offset, max + self.offset)
+    }
+}
+
+/// The union of two signed distance functions
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UnionSdf(pub Box<Sdf>, pub Box<Sdf>);
+
+impl SignedDistanceFunction for UnionSdf {
+    fn sdf(&self, pos: Vec3) -> f32 {
+        self.0.sdf(pos).min(self.1.sdf(pos))
+    }
+
+    fn aabb(&self) -> (Vec3, Vec3) {
+        let (a_min, a_max) = self.0.aabb();
+        let (b_min, b_max) = self.1.aabb();
+        (a_min.min(b_min), a_max.max(b_max))
+    }
+}
+
+/// The first signed distance function with the second one cut out of it
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SubtractSdf(pub Box<Sdf>, pub Box<Sdf>);
+
+impl SignedDistanceFunction for SubtractSdf {
+    fn sdf(&self, pos: Vec3) -> f32 {
+        self.0.sdf(pos).max(-self.1.sdf(pos))
+    }
+
+    fn aabb(&self) -> (Vec3, Vec3) {
+        self.0.aabb()
+    }
+}
+
+#[test]
+fn test_line_segment_sdf() {
+    let segment = LineSegmentSdf {
+        start: Vec3::ZERO,
+        end: Vec3::new(4., 0., 0.),
+        radius: 1.,
+    };
+    assert_eq!(-1., segment.sdf(Vec3::new(2., 0., 0.)));
+    assert_eq!(1., segment.sdf(Vec3::new(2., 2., 0.)));
+    assert_eq!(1., segment.sdf(Vec3::new(6., 0., 0.)));
+    assert_eq!(1., segment.sdf(Vec3::new(-2., 0., 0.)));
+    assert_eq!(
+        (Vec3::new(-1., -1., -1.), Vec3::new(5., 1., 1.)),
+        segment.aabb()
+    );
+}
+
+#[test]
+fn test_sphere_sdf() {
+    let sphere = SphereSdf(5.);
+    assert_eq!(-5., sphere.sdf(Vec3::new(0., 0., 0.)));
+    assert_eq!(-2., sphere.sdf(Vec3::new(2., 2., 2.)).round());
+    assert_eq!(4., sphere.sdf(Vec3::new(5., 5., 5.)).round());
+    assert_eq!(16., sphere.sdf(Vec3::new(20., 3., 7.)).round());
+}
+
+#[test]
+fn test_shell_sdf() {
+    let shell = ShellSdf {
+        radius: 5.,
+        thickness: 0.5,
+    };
+    assert_eq!(4.5, shell.sdf(Vec3::ZERO));
+    assert_eq!(-0.5, shell.sdf(Vec3::new(0., 5., 0.)));
+    assert!(shell.sdf(Vec3::new(3.3, 0., 4.4)).abs() < 0.01);
+    assert!(shell.sdf(Vec3::new(0., 0., -4.5)).abs() < 0.01);
+    assert_eq!((Vec3::splat(-5.5), Vec3::splat(5.5)), shell.aabb());
+}
+
+#[test]
+fn test_cylinder_sdf() {
+    let cylinder = CylinderSdf::new(2., 10.);
+    assert_eq!(Axis::Y, cylinder.axis);
+    assert_eq!(-2., cylinder.sdf(Vec3::ZERO));
+    assert_eq!(-1., cylinder.sdf(Vec3::new(0., 9., 0.)));
+    assert_eq!(1., cylinder.sdf(Vec3::new(0., 11., 0.)));
+    assert_eq!(1., cylinder.sdf(Vec3::new(3., 0., 0.)));
+
+    let (min, max) = cylinder.aabb();
+    assert_eq!(Vec3::new(-2., -10., -2.), min);
+    assert_eq!(Vec3::new(2., 10., 2.), max);
+
+    // Cylinders saved before they had an axis load as vertical cylinders
+    let cylinder: CylinderSdf = serde_json::from_str(r#"{"radius":2.0,"height":10.0}"#).unwrap();
+    assert_eq!(Axis::Y, cylinder.axis);
+}
+
+#[test]
+fn test_sdf_builder() {
+    let built = Sdf::Sphere(SphereSdf(2.))
+        .scaled(Vec3::new(1., 2., 1.))
+        .union(Sdf::Box(BoxSdf(Vec3::splat(1.5))));
+    let manual = Sdf::Union(UnionSdf(
+        Box::new(Sdf::Scaled(ScaledSdf {
+            inner: Box::new(Sdf::Sphere(SphereSdf(2.))),
+            scale: Vec3::new(1., 2., 1.),
+        })),
+        Box::new(Sdf::Box(BoxSdf(Vec3::splat(1.5)))),
+    ));
+
+    assert_eq!(manual.aabb(), built.aabb());
+    assert_eq!(
+        (Vec3::new(-2., -4., -2.), Vec3::new(2., 4., 2.)),
+        built.aabb()
+    );
+    for pos in [
+        Vec3::ZERO,
+        Vec3::new(0., 3., 0.),
+        Vec3::new(1.8, 0., 1.2),
+        Vec3::new(-4., 5., 2.),
+    ] {
+        assert_eq!(manual.sdf(pos), built.sdf(pos));
+    }
+    // Inside the stretched sphere but outside the box
+    assert!(built.sdf(Vec3::new(0., 3., 0.)) < 0.);
+}
+
+#[test]
+fn test_touches_chunk() {
+    let origin = ChunkPosition::new(0, 0, 0);
+    let touched = |sdf: Sdf, relative_pos: Vec3| {
+        let mut touched = Vec::new();
+        for x in -1..=1 {
+            for y in -1..=1 {
+                for z in -1..=1 {
+                    let chunk = ChunkPosition::new(x, y, z);
+                    if sdf.touches_chunk(relative_pos, chunk, origin) {
+                        touched.push(chunk);
+                    }
+                }
+            }
+        }
+        touched
+    };
+
+    assert_eq!(
+        vec![origin],
+        touched(SphereSdf(1.).into(), Vec3::new(7.5, 7.5, 7.5))
+    );
+    // The same sphere as in test_modify_two_chunk_border
+    assert_eq!(
+        vec![ChunkPosition::new(-1, 0, 0), origin],
+        touched(SphereSdf(2.).into(), Vec3::new(1., 10., 10.))
+    );
+}
+
+/// The mode to use for the editing operation
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Debug)]
+pub enum Mode {
+    /// Add the [SignedDistanceFunction] to the voxel grid
+    Add,
+    /// Remove the [SignedDistanceFunction] from the voxel grid
+    Remove,
+    /// Set every voxel inside the [SignedDistanceFunction] to [Voxel::AIR], ignoring the
+    /// smoothness. Unlike [Mode::Remove] this leaves no gradient at the edge of the shape
+    Clear,
+}
+
+#[test]
+fn test_mode_serde() {
+    for mode in [Mode::Add, Mode::Remove, Mode::Clear] {
+        let json = serde_json::to_string(&mode).unwrap();
+        assert_eq!(mode, serde_json::from_str::<Mode>(&json).unwrap());
+    }
+}
+
+#[test]
+fn test_clear_mode() {
+    let carve = |mode: Mode| {
+        let mut modifier = ChunkModifier::default();
+
+        let mut chunk_map = ChunkMap::default();
+        let mut world = World::default();
+        let mut query = world.query::<&ChunkData>();
+
+        modifier.apply_sdf(
+            ChunkPosition::new(0, 0, 0),
+            &mut chunk_map,
+            |entity| query.get(&world, entity).unwrap().expand(),
+            BoxSdf(Vec3::splat(10.)),
+            Mode::Add,
+            1,
+            0.,
+            Vec3::new(7.5, 7.5, 7.5),
+        );
+        modifier.apply_sdf(
+            ChunkPosition::new(0, 0, 0),
+            &mut chunk_map,
+            |entity| query.get(&world, entity).unwrap().expand(),
+            SphereSdf(3.),
+            mode,
+            1,
+            0.5,
+            Vec3::new(7.5, 7.5, 7.5),
+        );
+        let index = modifier.modified[&ChunkPosition::new(0, 0, 0)].index;
+        modifier.chunks.swap_remove(index)
+    };
+
+    let cleared = carve(Mode::Clear);
+    for x in 0..CHUNK_SIDES as i32 {
+        for y in 0..CHUNK_SIDES as i32 {
+            for z in 0..CHUNK_SIDES as i32 {
+                let voxel = cleared.get_voxel(x as u32, y as u32, z as u32);
+                if sample_pos(x, y, z, Vec3::splat(7.5)).length() < 3. {
+                    assert_eq!(Voxel::AIR, voxel);
+                } else {
+                    assert_eq!(Voxel::new(1, Voxel::MAX_VALUE), voxel);
+                }
+            }
+        }
+    }
+
+    // Just inside the sphere the removed voxel is only partially carved out
+    let removed = carve(Mode::Remove);
+    let value = removed.get_voxel(12, 9, 9).value();
+    assert!(value > 0 && value < Voxel::MAX_VALUE);
+}
+
+#[test]
+fn test_modify_single_chunk() {
+    let mut modifier = ChunkModifier::default();
+
+    let mut chunk_map = ChunkMap::default();
+    let mut world = World::default();
+    let mut query = world.query::<&ChunkData>();
+
+    modifier.apply_sdf(
+        ChunkPosition::new(-2, 1, 5),
+        &mut chunk_map,
+        |entity| query.get(&world, entity).unwrap().expand(),
+        SphereSdf(4.),
+        Mode::Add,
+        1,
+        0.01,
+        Vec3::new(6., 7., 8.),
+    );
+
+    modifier.apply_sdf(
+        ChunkPosition::new(-2, 1, 5),
+        &mut chunk_map,
+        |entity| query.get(&world, entity).unwrap().expand(),
+        SphereSdf(3.),
+        Mode::Remove,
+        1,
+        0.01,
+        Vec3::new(8., 6., 6.),
+    );
+
+    assert_eq!(1, modifier.modified.len());
+    assert_eq!(1, modifier.chunks.len());
+    assert!(modifier
+        .modified
+        .contains_key(&ChunkPosition::new(-2, 1, 5)));
+}
+
+#[test]
+fn test_add_blends_material() {
+    let mut modifier = ChunkModifier::default();
+
+    let mut chunk_map = ChunkMap::default();
+    let mut world = World::default();
+    let mut query = world.query::<&ChunkData>();
+
+    modifier.apply_sdf(
+        ChunkPosition::new(0, 0, 0),
+        &mut chunk_map,
+        |entity| query.get(&world, entity).unwrap().expand(),
+        BoxSdf(Vec3::new(7.5, 3., 7.5)),
+        Mode::Add,
+        1,
+        0.01,
+        Vec3::new(7.5, 3., 7.5),
+    );
+
+    modifier.apply_sdf(
+        ChunkPosition::new(0, 0, 0),
+        &mut chunk_map,
+        |entity| query.get(&world, entity).unwrap().expand(),
+        SphereSdf(3.),
+        Mode::Add,
+        2,
+        0.5,
+        Vec3::new(7.5, 4.5, 7.5),
+    );
+
+    let chunk = &modifier.chunks[modifier.modified[&ChunkPosition::new(0, 0, 0)].index];
+    // The core of the sphere
+    assert_eq!(2, chunk.get_voxel(9, 5, 9).material());
+    // Terrain far away from the sphere
+    assert_eq!(1, chunk.get_voxel(1, 1, 1).material());
+}
+
+#[test]
+fn test_hard_edges_without_smoothness() {
+    let mut modifier = ChunkModifier::default();
+
+    let mut chunk_map = ChunkMap::default();
+    let mut world = World::default();
+    let mut query = world.query::<&ChunkData>();
+
+    modifier.apply_sdf(
+        ChunkPosition::new(0, 0, 0),
+        &mut chunk_map,
+        |entity| query.get(&world, entity).unwrap().expand(),
+        BoxSdf(Vec3::splat(3.)),
+        Mode::Add,
+        1,
+        0.,
+        Vec3::new(7.5, 7.5, 7.5),
+    );
+
+    let chunk = &modifier.chunks[modifier.modified[&ChunkPosition::new(0, 0, 0)].index];
+    // The corner of the box lies exactly on the surface
+    assert_eq!(
+        Voxel::AIR.with_value_f32(0.).value(),
+        chunk.get_voxel(5, 5, 5).value()
+    );
+    // Diagonally outside the corner nothing gets rounded off
+    assert_eq!(0, chunk.get_voxel(4, 4, 4).value());
+    assert!(chunk.0.iter().all(|v| f32::from(*v).is_finite()));
+}
+
+#[test]
+fn ignore_non_finite_edits() {
+    let mut modifier = ChunkModifier::default();
+
+    let mut chunk_map = ChunkMap::default();
+    let mut world = World::default();
+    let mut query = world.query::<&ChunkData>();
+
+    modifier.apply_sdf(
+        ChunkPosition::new(0, 0, 0),
+        &mut chunk_map,
+        |entity| query.get(&world, entity).unwrap().expand(),
+        SphereSdf(2.),
+        Mode::Add,
+        1,
+        f32::NAN,
+        Vec3::new(10., 10., 10.),
+    );
+
+    modifier.apply_sdf(
+        ChunkPosition::new(0, 0, 0),
+        &mut chunk_map,
+        |entity| query.get(&world, entity).unwrap().expand(),
+        SphereSdf(2.),
+        Mode::Add,
+        1,
+        0.01,
+        Vec3::new(10., f32::NAN, 10.),
+    );
+
+    assert_eq!(0, modifier.modified.len());
+    assert_eq!(0, modifier.chunks.len());
+}
+
+#[test]
+fn test_preview_sdf() {
+    let mut modifier = ChunkModifier::default();
+
+    let mut chunk_map = ChunkMap::default();
+    let mut world = World::default();
+    let mut query = world.query::<&ChunkData>();
+
+    let preview = modifier.preview_sdf(
+        ChunkPosition::new(0, 0, 0),
+        &chunk_map,
+        |entity| query.get(&world, entity).unwrap().expand(),
+        SphereSdf(3.),
+        Mode::Add,
+        0.01,
+        Vec3::new(2., 10., 10.),
+    );
+    assert!(modifier.modified.is_empty());
+
+    modifier.apply_sdf(
+        ChunkPosition::new(0, 0, 0),
+        &mut chunk_map,
+        |entity| query.get(&world, entity).unwrap().expand(),
+        SphereSdf(3.),
+        Mode::Add,
+        1,
+        0.01,
+        Vec3::new(2., 10., 10.),
+    );
+    let filled = modifier
+        .chunks
+        .iter()
+        .flat_map(|chunk| chunk.0.iter())
+        .filter(|voxel| voxel.is_solid())
+        .count();
+    assert!(preview > 0);
+    assert_eq!(filled as i32, preview);
+
+    // Removing the same sphere again should remove everything that was added
+    let preview = modifier.preview_sdf(
+        ChunkPosition::new(0, 0, 0),
+        &chunk_map,
+        |entity| query.get(&world, entity).unwrap().expand(),
+        SphereSdf(3.),
+        Mode::Remove,
+        0.01,
+        Vec3::new(2., 10., 10.),
+    );
+    assert_eq!(-(filled as i32), preview);
+}
+
+#[test]
+fn test_remove_hollow() {
+    let mut modifier = ChunkModifier::default();
+
+    let mut chunk_map = ChunkMap::default();
+    let mut world = World::default();
+    let mut query = world.query::<&ChunkData>();
+
+    let center = Vec3::new(7.5, 7.5, 7.5);
+    modifier.apply_sdf(
+        ChunkPosition::new(0, 0, 0),
+        &mut chunk_map,
+        |entity| query.get(&world, entity).unwrap().expand(),
+        BoxSdf(Vec3::splat(7.5)),
+        Mode::Add,
+        1,
+        0.,
+        center,
+    );
+    modifier.remove_sdf(
+        ChunkPosition::new(0, 0, 0),
+        &mut chunk_map,
+        |entity| query.get(&world, entity).unwrap().expand(),
+        SphereSdf(5.),
+        Some(1.),
+        0.,
+        center,
+    );
+
+    let chunk = modifier
+        .modified_chunk(ChunkPosition::new(0, 0, 0))
+        .unwrap();
+    // The far interior of the sphere stays solid
+    assert!(chunk.get_voxel(9, 9, 9).is_solid());
+    // Just inside the surface the voxels are removed
+    assert!(!chunk.get_voxel(15, 9, 9).is_solid());
+    // Outside of the sphere nothing changes
+    assert!(chunk.get_voxel(0, 0, 0).is_solid());
+}
+
+#[test]
+fn test_apply_order() {
+    use bevy::ecs::system::CommandQueue;
+
+    let spawned = || {
+        let mut modifier = ChunkModifier::default();
+
+        let mut chunk_map = ChunkMap::default();
+        let mut world = World::default();
+        let mut query = world.query::<&ChunkData>();
+
+        modifier.apply_sdf(
+            ChunkPosition::new(0, 0, 0),
+            &mut chunk_map,
+            |entity| query.get(&world, entity).unwrap().expand(),
+            SphereSdf(17.),
+            Mode::Add,
+            1,
+            0.01,
+            Vec3::new(10., 10., 10.),
+        );
+
+        let mut queue = CommandQueue::default();
+        modifier.apply(&mut chunk_map, &mut Commands::new(&mut queue, &world));
+        queue.apply(&mut world);
+
+        world
+            .query::<&ChunkPosition>()
+            .iter(&world)
+            .copied()
+            .collect::<Vec<_>>()
+    };
+
+    let first = spawned();
+    assert_eq!(27, first.len());
+    assert!(first.windows(2).all(|w| w[0] < w[1]));
+    assert_eq!(first, spawned());
+}
+
+#[test]
+fn test_large_smoothness_has_no_cutoff() {
+    let mut modifier = ChunkModifier::default();
+    let mut chunk_map = ChunkMap::default();
+    let mut world = World::default();
+    let mut query = world.query::<&ChunkData>();
+
+    modifier.apply_sdf(
+        ChunkPosition::new(0, 0, 0),
+        &mut chunk_map,
+        |entity| query.get(&world, entity).unwrap().expand(),
+        SphereSdf(2.),
+        Mode::Add,
+        1,
+        4.,
+        Vec3::splat(7.5),
+    );
+
+    // The blend fades out before the corners of the chunk instead of stopping at the bounds of the
+    // sphere, so neighboring voxels never differ by more than the distance between them
+    let chunk = modifier
+        .modified_chunk(ChunkPosition::new(0, 0, 0))
+        .unwrap();
+    let max_step = VOXEL_SIZE + 2. / Voxel::MAX_VALUE as f32;
+    let value = |x, y, z| f32::from(chunk.get_voxel(x, y, z));
+    for z in 0..CHUNK_SIDES as u32 - 1 {
+        for y in 0..CHUNK_SIDES as u32 - 1 {
+            for x in 0..CHUNK_SIDES as u32 - 1 {
+                let v = value(x, y, z);
+                assert!((v - value(x + 1, y, z)).abs() <= max_step);
+                assert!((v - value(x, y + 1, z)).abs() <= max_step);
+                assert!((v - value(x, y, z + 1)).abs() <= max_step);
+            }
+        }
+    }
+    assert_eq!(Voxel::AIR, chunk.get_voxel(0, 0, 0));
+    assert!(chunk.get_voxel(9, 9, 9).is_solid());
+}
+
+#[test]
+fn test_apply_sphere_parity() {
+    let mut world = World::default();
+    let mut query = world.query::<&ChunkData>();
+
+    let mut generic = ChunkModifier::default();
+    let mut chunk_map = ChunkMap::default();
+    for (mode, material, center) in [
+        (Mode::Add, 1, Vec3::new(3., 12., 7.)),
+        (Mode::Remove, 1, Vec3::new(4., 11., 8.)),
+    ] {
+        generic.apply_sdf(
+            ChunkPosition::new(0, 0, 0),
+            &mut chunk_map,
+            |entity| query.get(&world, entity).unwrap().expand(),
+            SphereSdf(3.5),
+            mode,
+            material,
+            0.3,
+            center,
+        );
+    }
+
+    let mut sphere = ChunkModifier::default();
+    let mut chunk_map = ChunkMap::default();
+    for (mode, material, center) in [
+        (Mode::Add, 1, Vec3::new(3., 12., 7.)),
+        (Mode::Remove, 1, Vec3::new(4., 11., 8.)),
+    ] {
+        sphere.apply_sphere(
+            ChunkPosition::new(0, 0, 0),
+            &mut chunk_map,
+            |entity| query.get(&world, entity).unwrap().expand(),
+            center,
+            3.5,
+            mode,
+            material,
+            0.3,
+        );
+    }
+
+    assert_eq!(generic.modified.len(), sphere.modified.len());
+    for (pos, chunk) in generic.modified.iter() {
+        assert_eq!(
+            generic.chunks[chunk.index].0,
+            sphere.chunks[sphere.modified[pos].index].0
+        );
+    }
+}
+
+#[test]
+fn test_horizontal_cylinder() {
+    let mut modifier = ChunkModifier::default();
+
+    let mut chunk_map = ChunkMap::default();
+    let mut world = World::default();
+    let mut query = world.query::<&ChunkData>();
+
+    modifier.apply_sdf(
+        ChunkPosition::new(0, 0, 0),
+        &mut chunk_map,
+        |entity| query.get(&world, entity).unwrap().expand(),
+        CylinderSdf {
+            radius: 2.,
+            height: 5.,
+            axis: Axis::Z,
+        },
+        Mode::Add,
+        1,
+        0.,
+        Vec3::new(7.5, 7.5, 7.5),
+    );
+
+    let chunk = &modifier.chunks[modifier.modified[&ChunkPosition::new(0, 0, 0)].index];
+    // The voxels at 9, 9 are in the center of the cylinder, which spans from 2.5 to 12.5 along Z
+    let solid: Vec<_> = (0..CHUNK_SIDES as u32)
+        .filter(|z| chunk.get_voxel(9, 9, *z).is_solid())
+        .collect();
+    assert_eq!((3..=15).collect::<Vec<_>>(), solid);
+    // Perpendicular to the axis the cylinder only extends as far as its radius
+    assert!(chunk.get_voxel(9, 11, 9).is_solid());
+    assert!(!chunk.get_voxel(9, 12, 9).is_solid());
+    assert!(!chunk.get_voxel(9, 14, 9).is_solid());
+    assert!(chunk.get_voxel(11, 9, 9).is_solid());
+    assert!(!chunk.get_voxel(12, 9, 9).is_solid());
+}
+
+#[test]
+fn test_smooth_region_crosses_border() {
+    let mut world = World::default();
+    let mut chunk_map = ChunkMap::default();
+
+    let mut chunk = RawChunk::air();
+    for x in 0..3 {
+        chunk.set_voxel(x, 10, 10, Voxel::new(1, Voxel::MAX_VALUE));
+    }
+    let entity = world.spawn(ChunkData::from(chunk)).id();
+    chunk_map.insert(ChunkPosition::new(0, 0, 0), entity);
+
+    let mut query = world.query::<&ChunkData>();
+    let changed = smooth_region(
+        ChunkPosition::new(0, 0, 0),
+        &chunk_map,
+        |entity| query.get(&world, entity).unwrap().expand(),
+        2,
+    );
+
+    let get = |pos| changed.iter().find(|(p, _)| *p == pos).map(|(_, c)| c);
+    let center = get(ChunkPosition::new(0, 0, 0)).unwrap();
+    assert!(center.get_voxel(1, 10, 10).value() < Voxel::MAX_VALUE);
+    assert!(center.get_voxel(1, 11, 10).value() > 0);
+    // The spike touches the border, so the smoothing spreads into the neighboring chunk
+    let neighbor = get(ChunkPosition::new(-1, 0, 0)).unwrap();
+    assert!(neighbor.get_voxel(CHUNK_SIDES as u32 - 1, 10, 10).value() > 0);
+    // Chunks far away from the spike are left alone
+    assert!(get(ChunkPosition::new(1, 0, 0)).is_none());
+}
+
+#[test]
+fn test_apply_stamp() {
+    let mut world = World::default();
+    let mut query = world.query::<&ChunkData>();
+    let positions = [Vec3::new(7.5, 7.5, 7.5), Vec3::new(10.5, 7.5, 7.5)];
+
+    let mut uncached = ChunkModifier::default();
+    let mut chunk_map = ChunkMap::default();
+    for pos in positions {
+        uncached.apply_sdf(
+            ChunkPosition::new(0, 0, 0),
+            &mut chunk_map,
+            |entity| query.get(&world, entity).unwrap().expand(),
+            SphereSdf(3.),
+            Mode::Add,
+            1,
+            0.2,
+            pos,
+        );
     }
 
-    /// Apply a [SignedDistanceFunction] to the voxel grid at the specified position relative to
-    /// the given [ChunkPosition]
-    pub fn apply_sdf(
-        &mut self,
-        chunk_pos: ChunkPosition,
-        chunk_map: &mut ChunkMap,
-        mut chunks_getter: impl FnMut(Entity) -> RawChunk,
-        sdf: impl SignedDistanceFunction,
-        mode: Mode,
-        smoothness: f32,
-        relative_pos: Vec3,
-    ) {
-        let (aabb_min, aabb_max) = sdf.aabb();
+    let mut cache = StampCache::default();
+    let mut cached = ChunkModifier::default();
+    let mut chunk_map = ChunkMap::default();
+    for pos in positions {
+        cached.apply_stamp(
+            &mut cache,
+            ChunkPosition::new(0, 0, 0),
+            &mut chunk_map,
+            |entity| query.get(&world, entity).unwrap().expand(),
+            &Sdf::Sphere(SphereSdf(3.)),
+            Mode::Add,
+            1,
+            0.2,
+            pos,
+        );
+    }
 
-        let aabb_min = (aabb_min + relative_pos) / VOXEL_SIZE;
-        // let relative_pos = relative_pos + aabb_min.fract() * VOXEL_SIZE;
-        let aabb_min = aabb_min.floor();
-        let aabb_min = IVec3::new(aabb_min.x as i32, aabb_min.y as i32, aabb_min.z as i32) - 1;
+    // Both positions have the same offset from the voxel grid, so the stamp is reused
+    assert_eq!(1, cache.len());
+    assert_eq!(uncached.modified.len(), cached.modified.len());
+    for (pos, chunk) in uncached.modified.iter() {
+        assert_eq!(
+            uncached.chunks[chunk.index].0,
+            cached.chunks[cached.modified[pos].index].0
+        );
+    }
+}
 
-        let aabb_max = ((aabb_max + relative_pos) / VOXEL_SIZE).ceil();
-        let aabb_max = IVec3::new(aabb_max.x as i32, aabb_max.y as i32, aabb_max.z as i32) + 1;
+#[test]
+fn test_stroke_compresses_once() {
+    use bevy::ecs::system::CommandQueue;
 
-        for x in aabb_min.x..aabb_max.x {
-            for y in aabb_min.y..aabb_max.y {
-                for z in aabb_min.z..aabb_max.z {
-                    let Some(voxel) =
-                        self.get_voxel(chunk_pos, chunk_map, &mut chunks_getter, x, y, z)
-                    else {
-                        continue;
-                    };
-                    let cur_value = f32::from(*voxel);
-                    let new_value = sdf
-                        .sdf(Vec3::new(
-                            // TODO: Figure out a cleaner solution than this offset
-                            (x + 1) as f32 * VOXEL_SIZE - relative_pos.x,
-                            (y + 1) as f32 * VOXEL_SIZE - relative_pos.y,
-                            (z + 1) as f32 * VOXEL_SIZE - relative_pos.z,
-                        ))
-                        .clamp(-1., 1.);
-                    let value = match mode {
-                        Mode::Add => smin(cur_value, new_value, smoothness),
-                        Mode::Remove => smax(cur_value, -new_value, smoothness),
-                    };
-                    *voxel = voxel.with_value_f32(value.clamp(-1., 1.));
-                }
-            }
-        }
+    let mut world = World::default();
+    let mut chunk_map = ChunkMap::default();
+    let pos = ChunkPosition::new(0, 0, 0);
+    let entity = world.spawn((pos, ChunkData::air())).id();
+    chunk_map.insert(pos, entity);
+
+    let mut query = world.query::<&ChunkData>();
+    let mut expanded = HashMap::<Entity, usize>::default();
+    let mut modifier = ChunkModifier::default();
+    for i in 0..50 {
+        modifier.apply_sdf(
+            ChunkPosition::new(0, 0, 0),
+            &mut chunk_map,
+            |entity| {
+                *expanded.entry(entity).or_default() += 1;
+                query.get(&world, entity).unwrap().expand()
+            },
+            SphereSdf(3.),
+            Mode::Add,
+            1,
+            0.1,
+            Vec3::new(6. + i as f32 * 0.2, 10., 10.),
+        );
     }
-}
+    assert_eq!(Some(&1), expanded.get(&entity));
+    assert!(modifier.modified.len() > 1);
 
-// Polynomial smin from https://iquilezles.org/articles/smin
-#[inline(always)]
-fn smooth(a: f32, b: f32, k: f32) -> f32 {
-    let h = (k - (a - b).abs()).max(0.0);
-    h * h * 0.25 / k
-}
+    // Each changed chunk is compressed exactly once, no matter how many edits touched it
+    let changed = modifier.modified.values().filter(|m| m.changed).count();
+    let compressed = modifier.compress();
+    assert_eq!(changed, compressed.len());
+    assert!(compressed.windows(2).all(|w| w[0].0 < w[1].0));
+    assert_eq!(
+        Some(entity),
+        compressed.iter().find(|(p, ..)| *p == pos).unwrap().1
+    );
 
-#[inline(always)]
-fn smin(a: f32, b: f32, k: f32) -> f32 {
-    a.min(b) - smooth(a, b, k)
+    // Which is either inserted on its existing entity or on a single new entity
+    let mut queue = CommandQueue::default();
+    modifier.apply(&mut chunk_map, &mut Commands::new(&mut queue, &world));
+    queue.apply(&mut world);
+    let mut chunks = world.query::<(&ChunkPosition, &ChunkData)>();
+    assert_eq!(changed, chunks.iter(&world).count());
+    for (pos, data) in chunks.iter(&world) {
+        assert_eq!(
+            ChunkData::from(modifier.modified_chunk(*pos).unwrap()),
+            *data
+        );
+    }
 }
 
-#[inline(always)]
-fn smax(a: f32, b: f32, k: f32) -> f32 {
-    a.max(b) + smooth(a, b, k)
-}
+#[test]
+fn test_sub_quantum_edit_is_no_op() {
+    use bevy::ecs::system::CommandQueue;
 
-/// A trait for a signed distance function
-#[enum_dispatch]
-pub trait SignedDistanceFunction {
-    /// Get the sdf value at the provided position, with the shape at 0,0,0
-    fn sdf(&self, pos: Vec3) -> f32;
-    /// Get the bounding box for the shape
-    fn aabb(&self) -> (Vec3, Vec3);
-}
+    let mut chunk_map = ChunkMap::default();
+    let mut edit = |modifier: &mut ChunkModifier, radius, mode| {
+        modifier.apply_sdf(
+            ChunkPosition::new(0, 0, 0),
+            &mut chunk_map,
+            |_| unreachable!(),
+            SphereSdf(radius),
+            mode,
+            1,
+            0.,
+            Vec3::new(7., 7., 7.),
+        )
+    };
 
-/// An enum with SDF variants, used to pass the SignedDistanceFunction trait around without Box or
-/// dynamic dispatch
-#[derive(Debug)]
-#[enum_dispatch(SignedDistanceFunction)]
-pub enum Sdf {
-    /// A sphere
-    Sphere(SphereSdf),
-    /// A box
-    Box(BoxSdf),
-    /// A vertical cylinder
-    Cylinder(CylinderSdf),
+    let mut modifier = ChunkModifier::default();
+    assert!(edit(&mut modifier, 3., Mode::Add));
+    // Shrinking the brush by far less than a value step doesn't change any stored voxel
+    assert!(!edit(&mut modifier, 2.9999, Mode::Add));
+
+    // Chunks that were loaded but never changed are not applied
+    let mut unchanged = ChunkModifier::default();
+    assert!(!edit(&mut unchanged, 3., Mode::Remove));
+    assert!(!unchanged.modified.is_empty());
+
+    let mut world = World::default();
+    let mut queue = CommandQueue::default();
+    unchanged.apply(&mut chunk_map, &mut Commands::new(&mut queue, &world));
+    queue.apply(&mut world);
+    assert_eq!(0, world.entities().len());
 }
 
-/// A signed distance sphere
-#[derive(Debug)]
-pub struct SphereSdf(pub f32);
+#[test]
+fn test_no_op_keeps_air_mark() {
+    use crate::ChunkSlot;
+    use bevy::ecs::system::CommandQueue;
 
-impl SignedDistanceFunction for SphereSdf {
-    fn sdf(&self, pos: Vec3) -> f32 {
-        pos.length() - self.0
-    }
+    let mut chunk_map = ChunkMap::default();
+    let air = ChunkPosition::new(0, 0, 0);
+    chunk_map.mark_air(air);
 
-    fn aabb(&self) -> (Vec3, Vec3) {
-        (Vec3::splat(-self.0), Vec3::splat(self.0))
-    }
+    // Removing from an air chunk changes nothing, so the position still only holds air
+    let mut modifier = ChunkModifier::default();
+    assert!(!modifier.apply_sdf(
+        air,
+        &mut chunk_map,
+        |_| unreachable!(),
+        SphereSdf(3.),
+        Mode::Remove,
+        0,
+        0.,
+        Vec3::splat(7.5),
+    ));
+    let mut world = World::default();
+    let mut queue = CommandQueue::default();
+    modifier.apply(&mut chunk_map, &mut Commands::new(&mut queue, &world));
+    queue.apply(&mut world);
+    assert_eq!(0, world.entities().len());
+    assert_eq!(Some(ChunkSlot::Air), chunk_map.slot(air));
+
+    // Adding to it does spawn a chunk, which takes the place of the mark
+    assert!(modifier.apply_sdf(
+        air,
+        &mut chunk_map,
+        |_| unreachable!(),
+        SphereSdf(3.),
+        Mode::Add,
+        1,
+        0.,
+        Vec3::splat(7.5),
+    ));
+    modifier.apply(&mut chunk_map, &mut Commands::new(&mut queue, &world));
+    queue.apply(&mut world);
+    assert_eq!(1, world.entities().len());
+    let Some(ChunkSlot::Entity(entity)) = chunk_map.slot(air) else {
+        panic!("the spawned chunk isn't in the chunk map");
+    };
+    assert!(world.get::<ChunkData>(entity).is_some());
+
+    // A following edit writes to the spawned chunk instead of spawning another one
+    modifier.clear();
+    assert!(modifier.apply_sdf(
+        air,
+        &mut chunk_map,
+        |entity| world.get::<ChunkData>(entity).unwrap().expand(),
+        SphereSdf(2.),
+        Mode::Remove,
+        0,
+        0.,
+        Vec3::splat(7.5),
+    ));
+    modifier.apply(&mut chunk_map, &mut Commands::new(&mut queue, &world));
+    queue.apply(&mut world);
+    assert_eq!(1, world.entities().len());
+    assert_eq!(Some(ChunkSlot::Entity(entity)), chunk_map.slot(air));
 }
 
-/// A signed distance box
-#[derive(Debug)]
-pub struct BoxSdf(pub Vec3);
+#[test]
+fn test_apply_sdf_snapped() {
+    let mut chunk_map = ChunkMap::default();
+    let mut modifier = ChunkModifier::default();
+    assert!(modifier.apply_sdf_snapped(
+        ChunkPosition::new(0, 0, 0),
+        &mut chunk_map,
+        |_| unreachable!(),
+        SphereSdf(2.),
+        Mode::Add,
+        1,
+        0.,
+        Vec3::new(7.1, 7.3, 6.9),
+    ));
 
-impl SignedDistanceFunction for BoxSdf {
-    fn sdf(&self, pos: Vec3) -> f32 {
-        let q = pos.abs() - self.0;
-        q.max(Vec3::ZERO).length() + q.x.max(q.y).max(q.z).min(0.0)
+    // The center is snapped to (6.75, 7.5, 6.75), which voxel (8, 9, 8) is sampled at
+    let chunk = modifier
+        .modified_chunk(ChunkPosition::new(0, 0, 0))
+        .unwrap();
+    let center = IVec3::new(8, 9, 8);
+    let voxel = |offset: IVec3| {
+        let pos = (center + offset).as_uvec3();
+        chunk.get_voxel(pos.x, pos.y, pos.z)
+    };
+    assert!(voxel(IVec3::ZERO).is_solid());
+    for x in -4..=4 {
+        for y in -4..=4 {
+            for z in -4..=4 {
+                let offset = IVec3::new(x, y, z);
+                let value = voxel(offset).value();
+                assert_eq!(value, voxel(-offset).value());
+                assert_eq!(value, voxel(IVec3::new(y, z, x)).value());
+            }
+        }
     }
+}
 
-    fn aabb(&self) -> (Vec3, Vec3) {
-        (-self.0, self.0)
+#[test]
+fn test_apply_sdf_antialiased() {
+    use crate::surface_nets::generate_chunk;
+    use bevy::ecs::system::SystemState;
+
+    // A plane between voxel 14 and 15, with a shape that underestimates its distance by half
+    let relative_pos = Vec3::new(7.5, 7.5 + 0.3 * VOXEL_SIZE, 7.5);
+    let plane = || Sdf::from(BoxSdf(Vec3::new(20., 2., 20.))).scaled(Vec3::new(1., 2., 1.));
+    let true_height = relative_pos.y + 4. - crate::CHUNK_SIZE / 2.;
+
+    let mesh_error = |modifier: &ChunkModifier| {
+        let mut world = World::default();
+        let mut chunk_map = ChunkMap::default();
+        let chunk = modifier.modified_chunk(ChunkPosition::new(0, 0, 0));
+        let entity = world.spawn(ChunkData::from(chunk.unwrap())).id();
+        chunk_map.insert(ChunkPosition::new(0, 0, 0), entity);
+        let mut state: SystemState<Query<&ChunkData>> = SystemState::new(&mut world);
+        let query = state.get(&world);
+
+        let mut buffer = SurfaceNetsBuffer::default();
+        generate_chunk(
+            &mut buffer,
+            &mut SurroundingChunks::default(),
+            &mut Grid::default(),
+            ChunkPosition::new(0, 0, 0),
+            &chunk_map,
+            &query,
+        );
+        // Only the top of the plane, away from the borders of the chunk
+        let top: Vec<_> = buffer
+            .positions
+            .iter()
+            .filter(|p| p[0].abs() < 4. && p[2].abs() < 4. && p[1] > 0.)
+            .map(|p| (p[1] - true_height).abs())
+            .collect();
+        assert!(!top.is_empty());
+        top.into_iter().fold(0., f32::max)
+    };
+
+    let mut antialiased = ChunkModifier::default();
+    assert!(antialiased.apply_sdf_antialiased(
+        ChunkPosition::new(0, 0, 0),
+        &mut ChunkMap::default(),
+        |_| unreachable!(),
+        plane(),
+        Mode::Add,
+        1,
+        0.,
+        relative_pos,
+    ));
+    let mut plain = ChunkModifier::default();
+    assert!(plain.apply_sdf(
+        ChunkPosition::new(0, 0, 0),
+        &mut ChunkMap::default(),
+        |_| unreachable!(),
+        plane(),
+        Mode::Add,
+        1,
+        0.,
+        relative_pos,
+    ));
+
+    // The boundary voxels on both sides of the plane store their actual distance, while without
+    // anti-aliasing they store the underestimated one
+    let antialiased_chunk = antialiased
+        .modified_chunk(ChunkPosition::new(0, 0, 0))
+        .unwrap();
+    let plain_chunk = plain.modified_chunk(ChunkPosition::new(0, 0, 0)).unwrap();
+    for (y, distance) in [(14, -0.475), (15, 0.275)] {
+        assert!((((y + 1) as f32 * VOXEL_SIZE - relative_pos.y - 4.) - distance).abs() < 1e-4);
+        let expected = Voxel::new(1, 0).with_value_f32(distance);
+        let value = |chunk: &RawChunk| chunk.get_voxel(10, y, 10).value() as i32;
+        assert!((value(antialiased_chunk) - expected.value() as i32).abs() <= 1);
+        let underestimated = Voxel::new(1, 0).with_value_f32(distance / 2.);
+        assert!((value(plain_chunk) - underestimated.value() as i32).abs() <= 1);
     }
-}
 
-/// A signed distance cylinder
-#[derive(Debug)]
-pub struct CylinderSdf {
-    /// The radius of the cylinder
-    pub radius: f32,
-    /// The height of the cylinder
-    pub height: f32,
+    let antialiased = mesh_error(&antialiased);
+    assert!(antialiased < 0.01, "{antialiased}");
 }
 
-impl SignedDistanceFunction for CylinderSdf {
-    fn sdf(&self, pos: Vec3) -> f32 {
-        let d = Vec2::new(pos.xz().length(), pos.y).abs() - Vec2::new(self.radius, self.height);
-        d.x.max(d.y).min(0.0) + d.max(Vec2::ZERO).length()
-    }
+#[test]
+fn test_preview_mesh() {
+    use crate::surface_nets::generate_chunk_sized;
+    use bevy::ecs::system::{CommandQueue, SystemState};
 
-    fn aabb(&self) -> (Vec3, Vec3) {
-        (
-            Vec3::new(-self.radius, -self.height / 2., -self.radius),
-            Vec3::new(self.radius, self.height / 2., self.radius),
-        )
+    for voxel_size in [VoxelSize::default(), VoxelSize(Vec3::new(0.5, 1., 0.75))] {
+        let mut world = World::default();
+        let mut modifier = ChunkModifier::default();
+        modifier.set_voxel_size(voxel_size);
+        // The sphere lies on the border of two chunks, which both need the other's pending data
+        let chunk_size = voxel_size.chunk_size();
+        modifier.apply_sdf(
+            ChunkPosition::new(0, 0, 0),
+            &mut ChunkMap::default(),
+            |_| unreachable!(),
+            SphereSdf(3.),
+            Mode::Add,
+            1,
+            0.5,
+            Vec3::new(chunk_size.x, chunk_size.y / 2., chunk_size.z / 2.),
+        );
+
+        let positions = [ChunkPosition::new(0, 0, 0), ChunkPosition::new(1, 0, 0)];
+        let mut state: SystemState<Query<&ChunkData>> = SystemState::new(&mut world);
+        let previews: Vec<_> = positions
+            .iter()
+            .map(|pos| modifier.preview_mesh(*pos, &ChunkMap::default(), &state.get(&world)))
+            .collect();
+        assert_eq!(0, world.entities().len());
+
+        let mut queue = CommandQueue::default();
+        modifier.apply(
+            &mut ChunkMap::default(),
+            &mut Commands::new(&mut queue, &world),
+        );
+        queue.apply(&mut world);
+        let mut chunk_map = ChunkMap::default();
+        for (entity, pos) in world.query::<(Entity, &ChunkPosition)>().iter(&world) {
+            chunk_map.insert(*pos, entity);
+        }
+
+        let mut state: SystemState<Query<&ChunkData>> = SystemState::new(&mut world);
+        let query = state.get(&world);
+        for (pos, preview) in positions.into_iter().zip(previews) {
+            let mut buffer = SurfaceNetsBuffer::default();
+            assert!(generate_chunk_sized(
+                &mut buffer,
+                &mut SurroundingChunks::default(),
+                &mut Grid::default(),
+                pos,
+                &chunk_map,
+                &query,
+                voxel_size,
+            ));
+            assert_eq!(buffer.positions, preview.positions);
+            assert_eq!(buffer.normals, preview.normals);
+            assert_eq!(buffer.indices, preview.indices);
+        }
     }
 }
 
 #[test]
-fn test_sphere_sdf() {
-    let sphere = SphereSdf(5.);
-    assert_eq!(-5., sphere.sdf(Vec3::new(0., 0., 0.)));
-    assert_eq!(-2., sphere.sdf(Vec3::new(2., 2., 2.)).round());
-    assert_eq!(4., sphere.sdf(Vec3::new(5., 5., 5.)).round());
-    assert_eq!(16., sphere.sdf(Vec3::new(20., 3., 7.)).round());
-}
+fn test_clear_modifier() {
+    let mut world = World::default();
+    let mut query = world.query::<&ChunkData>();
+    let mut chunk_map = ChunkMap::default();
 
-/// The mode to use for the editing operation
-#[derive(Clone, Copy, Serialize, Deserialize, Debug)]
-pub enum Mode {
-    /// Add the [SignedDistanceFunction] to the voxel grid
-    Add,
-    /// Remove the [SignedDistanceFunction] from the voxel grid
-    Remove,
+    let mut edit = |modifier: &mut ChunkModifier, relative_pos| {
+        modifier.apply_sdf(
+            ChunkPosition::new(0, 0, 0),
+            &mut chunk_map,
+            |entity| query.get(&world, entity).unwrap().expand(),
+            SphereSdf(3.),
+            Mode::Add,
+            1,
+            0.1,
+            relative_pos,
+        );
+    };
+
+    let mut reused = ChunkModifier::default();
+    edit(&mut reused, Vec3::new(1., 10., 10.));
+    reused.clear();
+    assert!(reused.modified.is_empty());
+    assert!(reused.chunks.is_empty());
+    edit(&mut reused, Vec3::new(10., 10., 10.));
+
+    let mut fresh = ChunkModifier::default();
+    edit(&mut fresh, Vec3::new(10., 10., 10.));
+
+    assert_eq!(fresh.modified.len(), reused.modified.len());
+    for (pos, chunk) in fresh.modified.iter() {
+        assert_eq!(
+            fresh.chunks[chunk.index].0,
+            reused.modified_chunk(*pos).unwrap().0
+        );
+    }
 }
 
 #[test]
-fn test_modify_single_chunk() {
+fn test_sphere_on_chunk_corner() {
     let mut modifier = ChunkModifier::default();
 
     let mut chunk_map = ChunkMap::default();
     let mut world = World::default();
     let mut query = world.query::<&ChunkData>();
 
+    // The corner shared by the 8 chunks around the origin
+    let origin = ChunkPosition::new(0, 0, 0);
     modifier.apply_sdf(
-        ChunkPosition::new(-2, 1, 5),
-        &mut chunk_map,
-        |entity| query.get(&world, entity).unwrap().expand(),
-        SphereSdf(4.),
-        Mode::Add,
-        0.01,
-        Vec3::new(6., 7., 8.),
-    );
-
-    modifier.apply_sdf(
-        ChunkPosition::new(-2, 1, 5),
+        origin,
         &mut chunk_map,
         |entity| query.get(&world, entity).unwrap().expand(),
         SphereSdf(3.),
-        Mode::Remove,
-        0.01,
-        Vec3::new(8., 6., 6.),
+        Mode::Add,
+        1,
+        0.,
+        Vec3::ZERO,
     );
+    assert_eq!(8, modifier.modified.len());
 
-    assert_eq!(1, modifier.modified.len());
-    assert_eq!(1, modifier.chunks.len());
-    assert!(modifier
-        .modified
-        .contains_key(&ChunkPosition::new(-2, 1, 5)));
+    // Every voxel close enough to the sphere to get a value has it, on all sides of the corner
+    let mut checked = 0;
+    for x in -8..8 {
+        for y in -8..8 {
+            for z in -8..8 {
+                let distance = SphereSdf(3.).sdf(sample_pos(x, y, z, Vec3::ZERO));
+                if distance >= Voxel::MAX_DISTANCE {
+                    continue;
+                }
+                let (pos, [vx, vy, vz]) = locate(origin, x, y, z).unwrap();
+                let chunk = modifier.modified_chunk(pos).unwrap();
+                assert_eq!(
+                    Voxel::new(1, 0).with_value_f32(distance.clamp(-1., 1.)),
+                    chunk.get_voxel(vx, vy, vz),
+                );
+                checked += 1;
+            }
+        }
+    }
+    assert!(checked > 0);
 }
 
 #[test]
@@ -302,6 +2739,7 @@ fn test_modify_two_chunk_border() {
         |entity| query.get(&world, entity).unwrap().expand(),
         SphereSdf(2.),
         Mode::Add,
+        1,
         0.01,
         Vec3::new(1., 10., 10.),
     );
@@ -328,6 +2766,7 @@ fn test_modify_big_sdf() {
         |entity| query.get(&world, entity).unwrap().expand(),
         SphereSdf(11.),
         Mode::Add,
+        1,
         0.01,
         Vec3::new(10., 10., 10.),
     );
@@ -343,6 +2782,154 @@ fn test_modify_big_sdf() {
     }
 }
 
+#[test]
+fn test_apply_sdf_lod() {
+    use std::cell::Cell;
+
+    struct Counted<'a>(SphereSdf, &'a Cell<usize>);
+    impl SignedDistanceFunction for Counted<'_> {
+        fn sdf(&self, pos: Vec3) -> f32 {
+            self.1.set(self.1.get() + 1);
+            self.0.sdf(pos)
+        }
+        fn aabb(&self) -> (Vec3, Vec3) {
+            self.0.aabb()
+        }
+    }
+
+    let (full_calls, lod_calls) = (Cell::new(0), Cell::new(0));
+    let mut full = ChunkModifier::default();
+    let mut lod = ChunkModifier::default();
+    let mut chunk_map = ChunkMap::default();
+    for (radius, mode, relative_pos) in [
+        (11., Mode::Add, Vec3::new(10., 10., 10.)),
+        (6., Mode::Remove, Vec3::new(12., 7., 9.)),
+    ] {
+        full.apply_sdf(
+            ChunkPosition::new(0, 0, 0),
+            &mut chunk_map,
+            |_| unreachable!(),
+            Counted(SphereSdf(radius), &full_calls),
+            mode,
+            1,
+            0.3,
+            relative_pos,
+        );
+        lod.apply_sdf_lod(
+            ChunkPosition::new(0, 0, 0),
+            &mut chunk_map,
+            |_| unreachable!(),
+            Counted(SphereSdf(radius), &lod_calls),
+            mode,
+            1,
+            0.3,
+            relative_pos,
+        );
+    }
+
+    assert_eq!(27, lod.modified.len());
+    for (pos, chunk) in full.modified.iter() {
+        assert_eq!(
+            full.chunks[chunk.index].0,
+            lod.modified_chunk(*pos).unwrap().0
+        );
+    }
+    assert!(lod_calls.get() < full_calls.get());
+
+    // With large smoothness a second material blended over the first gets the same materials
+    let mut full = ChunkModifier::default();
+    let mut lod = ChunkModifier::default();
+    for (radius, material, relative_pos) in [
+        (6., 1, Vec3::new(7.5, 5., 7.5)),
+        (4., 2, Vec3::new(9., 9., 7.)),
+    ] {
+        full.apply_sdf(
+            ChunkPosition::new(0, 0, 0),
+            &mut chunk_map,
+            |_| unreachable!(),
+            SphereSdf(radius),
+            Mode::Add,
+            material,
+            4.,
+            relative_pos,
+        );
+        lod.apply_sdf_lod(
+            ChunkPosition::new(0, 0, 0),
+            &mut chunk_map,
+            |_| unreachable!(),
+            SphereSdf(radius),
+            Mode::Add,
+            material,
+            4.,
+            relative_pos,
+        );
+    }
+    assert_eq!(full.modified.len(), lod.modified.len());
+    for (pos, chunk) in full.modified.iter() {
+        assert_eq!(
+            full.chunks[chunk.index].0,
+            lod.modified_chunk(*pos).unwrap().0
+        );
+    }
+    let center = full.modified_chunk(ChunkPosition::new(0, 0, 0)).unwrap();
+    let materials: Vec<_> = center.0.iter().map(|v| v.material()).collect();
+    assert!(materials.contains(&1) && materials.contains(&2));
+}
+
+#[test]
+fn test_chunk_by_chunk_matches_per_voxel() {
+    let cases = [
+        (SphereSdf(2.), Vec3::new(1., 10., 10.)),
+        (SphereSdf(11.), Vec3::new(10., 10., 10.)),
+        (SphereSdf(4.), Vec3::new(-3., 14., 0.5)),
+    ];
+    for (sdf, relative_pos) in cases {
+        let mut modifier = ChunkModifier::default();
+
+        let mut chunk_map = ChunkMap::default();
+        let mut world = World::default();
+        let mut query = world.query::<&ChunkData>();
+
+        let origin = ChunkPosition::new(0, 0, 0);
+        modifier.apply_sdf(
+            origin,
+            &mut chunk_map,
+            |entity| query.get(&world, entity).unwrap().expand(),
+            SphereSdf(sdf.0),
+            Mode::Add,
+            1,
+            0.01,
+            relative_pos,
+        );
+
+        // Apply the sdf to every voxel separately, like edits were applied before
+        let mut expected = HashMap::<ChunkPosition, RawChunk>::default();
+        let (aabb_min, aabb_max) = voxel_bounds(sdf.aabb(), relative_pos);
+        for x in aabb_min.x..aabb_max.x {
+            for y in aabb_min.y..aabb_max.y {
+                for z in aabb_min.z..aabb_max.z {
+                    let (pos, [vx, vy, vz]) = locate(origin, x, y, z).unwrap();
+                    let chunk = expected.entry(pos).or_insert_with(RawChunk::air);
+                    let distance = sdf.sdf(sample_pos(x, y, z, relative_pos));
+                    apply_voxel(
+                        chunk.get_mut_voxel(vx, vy, vz),
+                        distance,
+                        Mode::Add,
+                        1,
+                        0.01,
+                        SmoothMode::Polynomial,
+                    );
+                }
+            }
+        }
+
+        assert_eq!(expected.len(), modifier.modified.len());
+        for (pos, chunk) in expected {
+            assert_eq!(chunk.0, modifier.modified_chunk(pos).unwrap().0);
+        }
+    }
+}
+
 #[test]
 fn ignore_out_of_bounds_edits() {
     let mut modifier = ChunkModifier::default();
@@ -357,6 +2944,7 @@ fn ignore_out_of_bounds_edits() {
         |entity| query.get(&world, entity).unwrap().expand(),
         SphereSdf(1.),
         Mode::Add,
+        1,
         0.01,
         Vec3::new(-5., -5., -5.),
     );
@@ -367,6 +2955,7 @@ fn ignore_out_of_bounds_edits() {
         |entity| query.get(&world, entity).unwrap().expand(),
         SphereSdf(1.),
         Mode::Add,
+        1,
         0.01,
         Vec3::new(20., 20., 20.),
     );
@@ -374,3 +2963,109 @@ fn ignore_out_of_bounds_edits() {
     assert_eq!(0, modifier.modified.len());
     assert_eq!(0, modifier.chunks.len());
 }
+
+#[test]
+fn test_carve_cave() {
+    let mut world = World::default();
+    let mut query = world.query::<&ChunkData>();
+    let mut chunk_map = ChunkMap::default();
+    for x in -2..=2 {
+        for y in -2..=2 {
+            for z in -2..=2 {
+                let pos = ChunkPosition::new(x, y, z);
+                let solid = RawChunk(vec![Voxel::new(1, Voxel::MAX_VALUE); crate::CHUNK_VOXELS]);
+                chunk_map.insert(pos, world.spawn(ChunkData::from(solid)).id());
+            }
+        }
+    }
+
+    let mut carve = |seed| {
+        let mut modifier = ChunkModifier::default();
+        assert!(modifier.carve_cave(
+            Vec3::new(1., 2., 3.),
+            seed,
+            20.,
+            1.5,
+            &mut chunk_map,
+            |entity| query.get(&world, entity).unwrap().expand(),
+        ));
+        let mut chunks: Vec<_> = modifier
+            .modified
+            .iter()
+            .filter(|(_, m)| m.changed)
+            .map(|(pos, m)| (*pos, modifier.chunks[m.index].0.clone()))
+            .collect();
+        chunks.sort_unstable_by_key(|(pos, _)| *pos);
+        chunks
+    };
+
+    let first = carve(42);
+    let second = carve(42);
+    assert!(!first.is_empty());
+    assert_eq!(
+        first.iter().map(|(pos, _)| *pos).collect::<Vec<_>>(),
+        second.iter().map(|(pos, _)| *pos).collect::<Vec<_>>()
+    );
+    assert!(first == second);
+    assert!(first != carve(43));
+}
+
+#[test]
+fn test_apply_sdf_checked() {
+    let mut modifier = ChunkModifier::default();
+    let mut chunk_map = ChunkMap::default();
+
+    let mut apply = |modifier: &mut ChunkModifier, chunk_pos, relative_pos, radius| {
+        modifier.apply_sdf_checked(
+            chunk_pos,
+            &mut chunk_map,
+            |_| unreachable!(),
+            SphereSdf(radius),
+            Mode::Add,
+            1,
+            0.,
+            relative_pos,
+        )
+    };
+
+    let corner = ChunkPosition::new(i8::MIN, i8::MIN, i8::MIN);
+    let Err(EditError::OutOfBounds { clipped_voxels }) =
+        apply(&mut modifier, corner, Vec3::splat(-5.), 1.)
+    else {
+        panic!("edit outside of the world should be out of bounds");
+    };
+    assert!(clipped_voxels > 0);
+    assert!(modifier.modified.is_empty());
+
+    // Partially outside of the world, only the voxels inside of it are applied
+    let summary = apply(&mut modifier, corner, Vec3::splat(1.), 2.).unwrap();
+    assert_eq!(1, summary.touched_chunks);
+    assert!(summary.clipped_voxels > 0);
+    assert!(summary.changed);
+
+    let summary = apply(
+        &mut modifier,
+        ChunkPosition::new(0, 0, 0),
+        Vec3::splat(7.5),
+        2.,
+    );
+    assert_eq!(0, summary.unwrap().clipped_voxels);
+
+    assert_eq!(
+        Err(EditError::NonFiniteInput),
+        apply(&mut modifier, corner, Vec3::splat(1.), f32::NAN)
+    );
+    assert_eq!(
+        Err(EditError::NegativeSmoothness),
+        modifier.apply_sdf_checked(
+            ChunkPosition::new(0, 0, 0),
+            &mut chunk_map,
+            |_| unreachable!(),
+            SphereSdf(2.),
+            Mode::Add,
+            1,
+            -0.5,
+            Vec3::splat(7.5),
+        )
+    );
+}
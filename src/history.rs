@@ -0,0 +1,169 @@
+//! This module contains a serializable history of edits, which can be saved instead of the chunk
+//! data and replayed to regenerate the world
+
+use crate::{
+    edit::{ChunkModifier, Mode, Sdf, SmoothMode},
+    ChunkMap, ChunkPosition, RawChunk,
+};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// A single edit, with the same arguments as [ChunkModifier::apply_sdf]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EditOp {
+    /// The chunk the position of the edit is relative to
+    pub chunk_pos: ChunkPosition,
+    /// The shape to apply
+    pub sdf: Sdf,
+    /// Whether the shape is added or removed
+    pub mode: Mode,
+    /// The material of added voxels
+    pub material: u8,
+    /// The distance over which the shape is blended with the terrain
+    pub smoothness: f32,
+    /// The function used to blend the shape with the terrain
+    #[serde(default)]
+    pub smooth_mode: SmoothMode,
+    /// The position of the shape relative to the chunk
+    pub relative_pos: Vec3,
+}
+
+impl EditOp {
+    /// Apply the edit to the modifier
+    pub fn apply(
+        &self,
+        modifier: &mut ChunkModifier,
+        chunk_map: &mut ChunkMap,
+        chunks_getter: impl FnMut(Entity) -> RawChunk,
+    ) {
+        modifier.set_smooth_mode(self.smooth_mode);
+        modifier.apply_sdf(
+            self.chunk_pos,
+            chunk_map,
+            chunks_getter,
+            &self.sdf,
+            self.mode,
+            self.material,
+            self.smoothness,
+            self.relative_pos,
+        );
+    }
+}
+
+/// An ordered list of edits. Edits are deterministic, so replaying the history on the same chunks
+/// always results in the same chunk data
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct EditHistory(pub Vec<EditOp>);
+
+impl EditHistory {
+    /// Add an edit to the end of the history
+    pub fn push(&mut self, op: EditOp) {
+        self.0.push(op);
+    }
+
+    /// Apply every edit in order with a single [ChunkModifier], and write the result to the world
+    /// trough [Commands]
+    pub fn replay(
+        &self,
+        chunk_map: &mut ChunkMap,
+        mut chunks_getter: impl FnMut(Entity) -> RawChunk,
+        commands: &mut Commands,
+    ) {
+        let mut modifier = ChunkModifier::default();
+        for op in &self.0 {
+            op.apply(&mut modifier, chunk_map, &mut chunks_getter);
+        }
+        modifier.apply(chunk_map, commands);
+    }
+}
+
+#[test]
+fn test_replay_history() {
+    use crate::{
+        edit::{BoxSdf, SphereSdf},
+        ChunkData,
+    };
+    use bevy::ecs::system::CommandQueue;
+
+    let ops = || {
+        vec![
+            EditOp {
+                chunk_pos: ChunkPosition::new(0, 0, 0),
+                sdf: BoxSdf(Vec3::new(10., 3., 10.)).into(),
+                mode: Mode::Add,
+                material: 1,
+                smoothness: 0.,
+                smooth_mode: SmoothMode::Polynomial,
+                relative_pos: Vec3::new(7.5, 3., 7.5),
+            },
+            EditOp {
+                chunk_pos: ChunkPosition::new(0, 0, 0),
+                sdf: SphereSdf(3.).into(),
+                mode: Mode::Add,
+                material: 2,
+                smoothness: 0.5,
+                smooth_mode: SmoothMode::Cubic,
+                relative_pos: Vec3::new(14., 5., 7.5),
+            },
+            EditOp {
+                chunk_pos: ChunkPosition::new(1, 0, 0),
+                sdf: Sdf::from(SphereSdf(2.)).scaled(Vec3::new(2., 1., 1.)),
+                mode: Mode::Remove,
+                material: 0,
+                smoothness: 0.25,
+                smooth_mode: SmoothMode::Exponential,
+                relative_pos: Vec3::new(1., 4., 7.5),
+            },
+        ]
+    };
+
+    let saved = |world: &mut World| {
+        let mut chunks: Vec<_> = world
+            .query::<(&ChunkPosition, &ChunkData)>()
+            .iter(world)
+            .map(|(pos, data)| (*pos, serde_json::to_vec(data).unwrap()))
+            .collect();
+        chunks.sort();
+        chunks
+    };
+
+    // The live edits
+    let mut world = World::default();
+    let mut chunk_map = ChunkMap::default();
+    let mut modifier = ChunkModifier::default();
+    let mut query = world.query::<&ChunkData>();
+    for op in ops() {
+        op.apply(&mut modifier, &mut chunk_map, |entity| {
+            query.get(&world, entity).unwrap().expand()
+        });
+    }
+    let mut queue = CommandQueue::default();
+    modifier.apply(&mut chunk_map, &mut Commands::new(&mut queue, &world));
+    queue.apply(&mut world);
+    let live = saved(&mut world);
+
+    // The replayed edits, after a round trip trough serde
+    let history = EditHistory(ops());
+    let history: EditHistory =
+        serde_json::from_str(&serde_json::to_string(&history).unwrap()).unwrap();
+    let mut world = World::default();
+    let mut chunk_map = ChunkMap::default();
+    let mut query = world.query::<&ChunkData>();
+    let mut queue = CommandQueue::default();
+    history.replay(
+        &mut chunk_map,
+        |entity| query.get(&world, entity).unwrap().expand(),
+        &mut Commands::new(&mut queue, &world),
+    );
+    queue.apply(&mut world);
+
+    assert!(!live.is_empty());
+    assert_eq!(live, saved(&mut world));
+    // The replayed chunks are in the chunk map, so later edits update them instead of spawning more
+    assert_eq!(live.len(), chunk_map.len());
+    for (pos, _) in &live {
+        let entity = chunk_map.get(pos).unwrap();
+        assert_eq!(Some(pos), world.get::<ChunkPosition>(*entity));
+    }
+}
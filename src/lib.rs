@@ -10,18 +10,32 @@
 #![allow(clippy::too_many_arguments)]
 
 mod voxel;
-pub use voxel::Voxel;
+pub use voxel::{value_to_f32, Voxel};
 
 mod raw;
-pub use raw::RawChunk;
+pub use raw::{OutOfBounds, RawChunk};
 
 pub mod surface_nets;
 
+#[cfg(feature = "marching-cubes")]
+pub mod dual_contouring;
+
 mod storage;
-pub use storage::{ChunkData, ChunkMap, ChunkPosition};
+pub use storage::{
+    chunk_load_order, chunks_in_aabb, compress_chunks, decode_positions, encode_positions,
+    materials_in_region, prune_empty_chunks, ChunkCache, ChunkData, ChunkDataError, ChunkMap,
+    ChunkPosition, ChunkSlot, ChunkStats, OccupancyMask,
+};
+
+mod metadata;
+pub use metadata::{tag_new_chunks, ChunkMetadata, ChunkVersion};
 
 pub mod edit;
 
+pub mod query;
+
+pub mod history;
+
 use bevy::prelude::*;
 use fast_surface_nets::ndshape::ConstShape3u8;
 pub use fast_surface_nets::ndshape::{RuntimeShape, Shape};
@@ -32,11 +46,60 @@ pub const FETCH_SHAPE: ConstShape3u8<3, 3, 3> = ConstShape3u8::<3, 3, 3>;
 /// The size of each voxel, in meters.
 /// Each side of the voxel has the same size, making is a perfect cube
 pub const VOXEL_SIZE: f32 = 0.75;
+/// The size of each voxel along each axis, in meters, for voxels that aren't perfect cubes.
+/// Voxels are indexed the same way no matter their size, only where they lie in the world changes.
+/// Meshes made with [surface_nets::generate_chunk_sized], normals from
+/// [surface_nets::gradient_normals_sized] and edits made by a [edit::ChunkModifier] with
+/// [edit::ChunkModifier::set_voxel_size] use it. The queries in [query] and
+/// [ChunkPosition::from_transform] always assume cubes of [VOXEL_SIZE], use
+/// [VoxelSize::relative_position] to place edits in worlds with other sizes. The default is a cube
+/// of [VOXEL_SIZE]
+#[derive(Resource, Clone, Copy, Debug, PartialEq, Deref)]
+pub struct VoxelSize(pub Vec3);
+
+impl Default for VoxelSize {
+    fn default() -> Self {
+        Self(Vec3::splat(VOXEL_SIZE))
+    }
+}
+
+impl VoxelSize {
+    /// Get the size of a chunk along each axis, in meters
+    pub fn chunk_size(&self) -> Vec3 {
+        self.0 * CHUNK_SIDES as f32
+    }
+
+    /// Get the desired Transform translation for the chunk, like [ChunkPosition::get_translation]
+    pub fn translation(&self, chunk: ChunkPosition) -> Vec3 {
+        Vec3::new(chunk[0] as f32, chunk[1] as f32, chunk[2] as f32) * self.chunk_size()
+    }
+
+    /// Get the chunk a world-space translation is in, along with its position relative to that
+    /// chunk, like [ChunkPosition::from_transform] but for voxels of this size. Returns None for
+    /// translations outside of the world
+    pub fn relative_position(&self, translation: Vec3) -> Option<(ChunkPosition, Vec3)> {
+        let chunk_size = self.chunk_size();
+        let chunks = translation / chunk_size;
+        let range = WORLD_MIN_CHUNK as f32..WORLD_MAX_CHUNK as f32;
+        if !chunks.to_array().iter().all(|v| range.contains(v)) {
+            return None;
+        }
+        let chunks = chunks.round();
+        let chunk = ChunkPosition::new(chunks.x as i8, chunks.y as i8, chunks.z as i8);
+        let relative = translation - self.translation(chunk) + chunk_size / 2.;
+        Some((chunk, relative))
+    }
+}
+
 /// The number of voxels per side of a chunk
 pub const CHUNK_SIDES: usize = 20;
 pub(crate) const CHUNK_BOUNDS: u32 = CHUNK_SIDES as u32;
 /// The size of a chunk, in meters
 pub const CHUNK_SIZE: f32 = CHUNK_SIDES as f32 * VOXEL_SIZE;
+/// The lowest valid chunk coordinate on each axis
+pub const WORLD_MIN_CHUNK: i32 = i8::MIN as i32;
+/// The highest valid chunk coordinate on each axis
+pub const WORLD_MAX_CHUNK: i32 = i8::MAX as i32;
 /// The number of voxels per chunk, since every chunk is a cube of voxels this is
 /// just CHUNK_SIDES^3
 const CHUNK_VOXELS: usize = CHUNK_SIDES * CHUNK_SIDES * CHUNK_SIDES;
@@ -0,0 +1,121 @@
+use crate::{ChunkData, RawChunk, CHUNK_BOUNDS};
+
+use bevy::{prelude::*, utils::HashMap};
+
+/// Per-voxel gameplay data for a chunk, like marking a voxel as a door. This is stored separately
+/// from the [crate::ChunkData] so it doesn't take up any of the bits of a voxel, and is only
+/// allocated for chunks that actually have metadata
+#[derive(Component, Clone, Debug, Default)]
+pub struct ChunkMetadata(HashMap<[u8; 3], u32>);
+
+impl ChunkMetadata {
+    fn key(x: u32, y: u32, z: u32) -> [u8; 3] {
+        debug_assert!(x < CHUNK_BOUNDS && y < CHUNK_BOUNDS && z < CHUNK_BOUNDS);
+        [x as u8, y as u8, z as u8]
+    }
+
+    /// Get the metadata of the voxel at the specified coordinates
+    pub fn get_meta(&self, x: u32, y: u32, z: u32) -> Option<u32> {
+        self.0.get(&Self::key(x, y, z)).copied()
+    }
+
+    /// Set the metadata of the voxel at the specified coordinates
+    pub fn set_meta(&mut self, x: u32, y: u32, z: u32, meta: u32) {
+        self.0.insert(Self::key(x, y, z), meta);
+    }
+
+    /// Remove the metadata of the voxel at the specified coordinates, returning the old value
+    pub fn clear_meta(&mut self, x: u32, y: u32, z: u32) -> Option<u32> {
+        self.0.remove(&Self::key(x, y, z))
+    }
+
+    /// Remove the metadata of every voxel that is not solid in the given chunk. This can be used
+    /// after an edit to clear the metadata of removed voxels, see
+    /// [crate::edit::ChunkModifier::modified_chunk]
+    pub fn retain_solid(&mut self, chunk: &RawChunk) {
+        self.0
+            .retain(|[x, y, z], _| chunk.get_voxel(*x as u32, *y as u32, *z as u32).is_solid());
+    }
+
+    /// The number of voxels with metadata
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Check if no voxel has metadata
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+/// The version of the world generation a chunk was generated with. This is separate from the
+/// [ChunkData], so chunks generated by an outdated generator can be found and regenerated after
+/// the generator or save format changes
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ChunkVersion(pub u32);
+
+impl ChunkVersion {
+    /// Check if the chunk was generated with an older version than the current one
+    pub fn needs_regen(&self, current_version: u32) -> bool {
+        self.0 < current_version
+    }
+}
+
+/// Newly spawned chunks that don't have a [ChunkVersion] yet
+type UntaggedChunks<'w, 's> = Query<'w, 's, Entity, (Added<ChunkData>, Without<ChunkVersion>)>;
+
+/// Create a system that tags every newly spawned chunk without a [ChunkVersion] with the given
+/// version, so chunks don't need to be tagged everywhere they are spawned
+pub fn tag_new_chunks(version: u32) -> impl FnMut(Commands, UntaggedChunks) {
+    move |mut commands, chunks| {
+        for entity in chunks.iter() {
+            commands.entity(entity).insert(ChunkVersion(version));
+        }
+    }
+}
+
+#[test]
+fn test_metadata() {
+    let mut meta = ChunkMetadata::default();
+    assert_eq!(None, meta.get_meta(1, 2, 3));
+
+    meta.set_meta(1, 2, 3, 42);
+    meta.set_meta(19, 0, 7, 7);
+    assert_eq!(Some(42), meta.get_meta(1, 2, 3));
+    assert_eq!(Some(7), meta.get_meta(19, 0, 7));
+    assert_eq!(None, meta.get_meta(3, 2, 1));
+
+    assert_eq!(Some(42), meta.clear_meta(1, 2, 3));
+    assert_eq!(None, meta.get_meta(1, 2, 3));
+    assert_eq!(1, meta.len());
+}
+
+#[test]
+fn test_metadata_retain_solid() {
+    use crate::Voxel;
+
+    let mut chunk = RawChunk::air();
+    chunk.set_voxel(1, 1, 1, Voxel::new(1, Voxel::MAX_VALUE));
+
+    let mut meta = ChunkMetadata::default();
+    meta.set_meta(1, 1, 1, 1);
+    meta.set_meta(2, 2, 2, 2);
+    meta.retain_solid(&chunk);
+    assert_eq!(Some(1), meta.get_meta(1, 1, 1));
+    assert_eq!(None, meta.get_meta(2, 2, 2));
+}
+
+#[test]
+fn test_chunk_version() {
+    let mut world = World::default();
+    let old = world.spawn((ChunkData::air(), ChunkVersion(1))).id();
+    let new = world.spawn(ChunkData::air()).id();
+
+    let mut schedule = Schedule::default();
+    schedule.add_systems(tag_new_chunks(3));
+    schedule.run(&mut world);
+
+    assert_eq!(Some(&ChunkVersion(3)), world.get::<ChunkVersion>(new));
+    assert!(world.get::<ChunkVersion>(old).unwrap().needs_regen(3));
+    assert!(!world.get::<ChunkVersion>(new).unwrap().needs_regen(3));
+}
@@ -0,0 +1,378 @@
+//! This module contains queries on the voxel grid in world space. The queries assume voxels are
+//! cubes of [VOXEL_SIZE], they don't support a [crate::VoxelSize]
+
+use crate::{ChunkMap, ChunkPosition, RawChunk, Voxel, CHUNK_SIDES, CHUNK_SIZE, VOXEL_SIZE};
+
+use bevy::{prelude::*, utils::HashMap};
+
+/// Reads voxels by their global voxel coordinates, expanding every chunk at most once
+struct VoxelReader<'a, F: FnMut(Entity) -> RawChunk> {
+    chunk_map: &'a ChunkMap,
+    chunks_getter: F,
+    chunks: HashMap<ChunkPosition, Option<RawChunk>>,
+}
+
+impl<'a, F: FnMut(Entity) -> RawChunk> VoxelReader<'a, F> {
+    fn new(chunk_map: &'a ChunkMap, chunks_getter: F) -> Self {
+        Self {
+            chunk_map,
+            chunks_getter,
+            chunks: HashMap::default(),
+        }
+    }
+
+    /// Get the voxel at the global voxel coordinates, voxels in missing chunks or outside of the
+    /// world are air
+    fn voxel(&mut self, global: IVec3) -> Voxel {
+        let Some((chunk_pos, [x, y, z])) = split_global(global) else {
+            return Voxel::AIR;
+        };
+        let (chunk_map, chunks_getter) = (self.chunk_map, &mut self.chunks_getter);
+        let chunk = self
+            .chunks
+            .entry(chunk_pos)
+            .or_insert_with(|| chunk_map.get(&chunk_pos).map(|e| chunks_getter(*e)));
+        let Some(chunk) = chunk else {
+            return Voxel::AIR;
+        };
+        chunk.get_voxel(x, y, z)
+    }
+
+    /// Sample the signed distance value at a world position, interpolating between the 8
+    /// surrounding voxels
+    fn sample(&mut self, pos: Vec3) -> f32 {
+        let grid = voxel_space(pos);
+        let base = grid.floor();
+        let t = grid - base;
+        let base = IVec3::new(base.x as i32, base.y as i32, base.z as i32);
+
+        let mut value = 0.;
+        for i in 0..8 {
+            let offset = IVec3::new(i & 1, (i >> 1) & 1, (i >> 2) & 1);
+            let weight = Vec3::select(offset.cmpeq(IVec3::ONE), t, Vec3::ONE - t);
+            value += weight.x * weight.y * weight.z * f32::from(self.voxel(base + offset));
+        }
+        value
+    }
+
+    /// Get the gradient of the signed distance value at a world position, using central
+    /// differences half a voxel apart
+    fn gradient(&mut self, pos: Vec3) -> Vec3 {
+        const H: f32 = VOXEL_SIZE / 2.;
+        let mut axis = |offset: Vec3| self.sample(pos + offset) - self.sample(pos - offset);
+        Vec3::new(axis(Vec3::X * H), axis(Vec3::Y * H), axis(Vec3::Z * H)) / (2. * H)
+    }
+
+    /// Get the global voxel coordinates of the solid voxel closest to a world position, out of
+    /// the 8 voxels surrounding it
+    fn nearest_solid(&mut self, pos: Vec3) -> Option<(IVec3, Voxel)> {
+        let grid = voxel_space(pos);
+        let base = grid.floor();
+        let base = IVec3::new(base.x as i32, base.y as i32, base.z as i32);
+
+        let mut nearest = None;
+        let mut nearest_distance = f32::INFINITY;
+        for i in 0..8 {
+            let global = base + IVec3::new(i & 1, (i >> 1) & 1, (i >> 2) & 1);
+            let voxel = self.voxel(global);
+            let distance = global.as_vec3().distance_squared(grid);
+            if voxel.is_solid() && distance < nearest_distance {
+                nearest = Some((global, voxel));
+                nearest_distance = distance;
+            }
+        }
+        nearest
+    }
+}
+
+/// Split global voxel coordinates into the chunk that holds the voxel and the position of the voxel
+/// within that chunk. Returns None if the voxel lies outside of the world
+fn split_global(global: IVec3) -> Option<(ChunkPosition, [u32; 3])> {
+    const SIZE: i32 = CHUNK_SIDES as i32;
+    let chunk = global.div_euclid(IVec3::splat(SIZE));
+    if !ChunkPosition::is_valid(chunk.x, chunk.y, chunk.z) {
+        return None;
+    }
+    let local = global.rem_euclid(IVec3::splat(SIZE));
+    Some((
+        ChunkPosition::new(chunk.x as i8, chunk.y as i8, chunk.z as i8),
+        [local.x as u32, local.y as u32, local.z as u32],
+    ))
+}
+
+/// Convert a world position to global voxel coordinates, where voxel 0 of chunk 0 lies at 0.
+/// Voxels are placed the same way as the vertices of a chunk mesh
+fn voxel_space(pos: Vec3) -> Vec3 {
+    (pos + CHUNK_SIZE / 2.) / VOXEL_SIZE - 1.
+}
+
+/// Sample the normalized signed distance value at a world position, trilinearly interpolated
+/// between the surrounding voxels. Chunk boundaries are crossed as needed
+pub fn sample_sdf(
+    pos: Vec3,
+    chunk_map: &ChunkMap,
+    chunks_getter: impl FnMut(Entity) -> RawChunk,
+) -> f32 {
+    VoxelReader::new(chunk_map, chunks_getter).sample(pos)
+}
+
+/// Get the chunk and the position within that chunk of the voxel closest to a world position, for
+/// example to show which voxel a ray hit. Voxels are placed the same way as the vertices of the
+/// chunk meshes, which are offset by `-CHUNK_SIZE / 2`. Returns None outside of the world
+pub fn world_to_voxel(pos: Vec3) -> Option<(ChunkPosition, [u32; 3])> {
+    if !pos.is_finite() {
+        return None;
+    }
+    let global = voxel_space(pos).round();
+    split_global(IVec3::new(
+        global.x as i32,
+        global.y as i32,
+        global.z as i32,
+    ))
+}
+
+/// Get the approximate signed distance from a world position to the surface in meters, positive
+/// outside and negative inside. Like [Voxel::sdf_meters], distances saturate at
+/// [Voxel::MAX_DISTANCE]
+pub fn distance_to_surface(
+    pos: Vec3,
+    chunk_map: &ChunkMap,
+    chunks_getter: impl FnMut(Entity) -> RawChunk,
+) -> f32 {
+    sample_sdf(pos, chunk_map, chunks_getter) * Voxel::MAX_DISTANCE
+}
+
+/// Get the gradient of the normalized signed distance value at a world position, per meter. It
+/// points away from the surface, so normalizing it near the surface gives the surface normal
+pub fn sdf_gradient(
+    pos: Vec3,
+    chunk_map: &ChunkMap,
+    chunks_getter: impl FnMut(Entity) -> RawChunk,
+) -> Vec3 {
+    VoxelReader::new(chunk_map, chunks_getter).gradient(pos)
+}
+
+/// A surface hit by a ray, see [raycast_full]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RayHit {
+    /// The world position where the ray crosses the surface
+    pub position: Vec3,
+    /// The normal of the surface at the hit position, see [sdf_gradient]
+    pub normal: Vec3,
+    /// The material of the solid voxel closest to the hit position
+    pub material: u8,
+    /// The chunk that holds the solid voxel closest to the hit position
+    pub chunk: ChunkPosition,
+    /// The position of the solid voxel closest to the hit position, within its chunk
+    pub local_voxel: [u32; 3],
+}
+
+/// Cast a ray trough the voxel grid, returning the first surface it hits within `max_dist` meters.
+/// The ray is sphere traced using the signed distance values, then refined to the point where it
+/// crosses the surface. Rays that start inside the surface hit at their origin
+pub fn raycast_full(
+    origin: Vec3,
+    dir: Vec3,
+    max_dist: f32,
+    chunk_map: &ChunkMap,
+    chunks_getter: impl FnMut(Entity) -> RawChunk,
+) -> Option<RayHit> {
+    spherecast(origin, dir, 0., max_dist, chunk_map, chunks_getter)
+}
+
+/// Sweep a sphere trough the voxel grid, returning the first surface it touches within `max_dist`
+/// meters. This is like [raycast_full], but the sphere hits when its center comes within `radius`
+/// of the surface, so fast projectiles can't pass trough gaps or thin walls a ray would miss. The
+/// hit position is the point on the surface the sphere touches. Since signed distances saturate at
+/// [Voxel::MAX_DISTANCE], the radius should be smaller than that
+pub fn spherecast(
+    origin: Vec3,
+    dir: Vec3,
+    radius: f32,
+    max_dist: f32,
+    chunk_map: &ChunkMap,
+    chunks_getter: impl FnMut(Entity) -> RawChunk,
+) -> Option<RayHit> {
+    // The smallest step taken, so the sphere doesn't stall when grazing the surface
+    const MIN_STEP: f32 = VOXEL_SIZE / 16.;
+    const REFINE_STEPS: usize = 8;
+
+    let dir = dir.normalize_or_zero();
+    if !origin.is_finite() || !max_dist.is_finite() || !radius.is_finite() || dir == Vec3::ZERO {
+        return None;
+    }
+
+    let mut reader = VoxelReader::new(chunk_map, chunks_getter);
+    let mut clearance = |t: f32| reader.sample(origin + dir * t) * Voxel::MAX_DISTANCE - radius;
+    let (mut outside, mut t) = (0., 0.);
+    loop {
+        let distance = clearance(t);
+        if distance <= 0. {
+            break;
+        }
+        if t >= max_dist {
+            return None;
+        }
+        outside = t;
+        t = (t + distance.max(MIN_STEP)).min(max_dist);
+    }
+
+    let mut inside = t;
+    if inside > 0. {
+        for _ in 0..REFINE_STEPS {
+            let mid = (outside + inside) / 2.;
+            if clearance(mid) > 0. {
+                outside = mid;
+            } else {
+                inside = mid;
+            }
+        }
+    }
+
+    let center = origin + dir * inside;
+    let normal = reader.gradient(center).normalize_or_zero();
+    let position = center - normal * radius;
+    let (global, voxel) = reader.nearest_solid(position).unwrap_or_else(|| {
+        let global = voxel_space(position).round();
+        let global = IVec3::new(global.x as i32, global.y as i32, global.z as i32);
+        (global, reader.voxel(global))
+    });
+    let (chunk, local_voxel) = split_global(global)?;
+    Some(RayHit {
+        position,
+        normal,
+        material: voxel.material(),
+        chunk,
+        local_voxel,
+    })
+}
+
+#[cfg(test)]
+fn floor_world(material: u8) -> (World, ChunkMap) {
+    use crate::ChunkData;
+
+    // A floor at a height of 0, at the center of chunk 0
+    let mut chunk = RawChunk::air();
+    for x in 0..CHUNK_SIDES as u32 {
+        for y in 0..CHUNK_SIDES as u32 {
+            for z in 0..CHUNK_SIDES as u32 {
+                let height = (y + 1) as f32 * VOXEL_SIZE - CHUNK_SIZE / 2.;
+                chunk.set_voxel(
+                    x,
+                    y,
+                    z,
+                    Voxel::new(material, 0).with_value_f32(height.clamp(-1., 1.)),
+                );
+            }
+        }
+    }
+
+    let mut world = World::default();
+    let mut chunk_map = ChunkMap::default();
+    let pos = ChunkPosition::new(0, 0, 0);
+    let entity = world.spawn((pos, ChunkData::from(chunk))).id();
+    chunk_map.insert(pos, entity);
+    (world, chunk_map)
+}
+
+#[test]
+fn test_distance_to_surface() {
+    use crate::ChunkData;
+
+    let (mut world, chunk_map) = floor_world(1);
+    let mut query = world.query::<&ChunkData>();
+    let mut distance = |pos| {
+        distance_to_surface(pos, &chunk_map, |entity| {
+            query.get(&world, entity).unwrap().expand()
+        })
+    };
+
+    assert!((distance(Vec3::new(1.1, VOXEL_SIZE, -2.3)) - VOXEL_SIZE).abs() < 0.01);
+    assert!(distance(Vec3::new(0.5, 0., 0.5)).abs() < 0.01);
+    assert!((distance(Vec3::new(0.5, -0.3, 0.5)) + 0.3).abs() < 0.01);
+}
+
+#[test]
+fn test_raycast_full() {
+    use crate::ChunkData;
+
+    let (mut world, chunk_map) = floor_world(3);
+    let mut query = world.query::<&ChunkData>();
+    let mut raycast = |origin, dir, max_dist| {
+        raycast_full(origin, dir, max_dist, &chunk_map, |entity| {
+            query.get(&world, entity).unwrap().expand()
+        })
+    };
+
+    let hit = raycast(Vec3::new(0.5, 5., 0.5), Vec3::NEG_Y, 10.).unwrap();
+    assert!(hit.position.y.abs() < 0.01);
+    assert!(hit.normal.dot(Vec3::Y) > 0.99);
+    assert_eq!(3, hit.material);
+    assert_eq!(ChunkPosition::new(0, 0, 0), hit.chunk);
+    assert_eq!(9, hit.local_voxel[1]);
+
+    assert_eq!(None, raycast(Vec3::new(0.5, 5., 0.5), Vec3::NEG_Y, 2.));
+    assert_eq!(None, raycast(Vec3::new(0.5, 5., 0.5), Vec3::Y, 10.));
+}
+
+#[test]
+fn test_spherecast_thin_wall() {
+    use crate::ChunkData;
+
+    // A wall 0.4m thick and 2m high, standing on the x = 0 plane
+    let mut chunk = RawChunk::air();
+    for x in 0..CHUNK_SIDES as u32 {
+        for y in 0..CHUNK_SIDES as u32 {
+            for z in 0..CHUNK_SIDES as u32 {
+                let pos =
+                    (Vec3::new(x as f32, y as f32, z as f32) + 1.) * VOXEL_SIZE - CHUNK_SIZE / 2.;
+                let distance = (pos.x.abs() - 0.2).max(pos.y - 2.);
+                chunk.set_voxel(
+                    x,
+                    y,
+                    z,
+                    Voxel::new(4, 0).with_value_f32(distance.clamp(-1., 1.)),
+                );
+            }
+        }
+    }
+    let mut world = World::default();
+    let mut chunk_map = ChunkMap::default();
+    let pos = ChunkPosition::new(0, 0, 0);
+    let entity = world.spawn((pos, ChunkData::from(chunk))).id();
+    chunk_map.insert(pos, entity);
+
+    let mut query = world.query::<&ChunkData>();
+    let mut cast = |radius| {
+        spherecast(
+            Vec3::new(-5., 2.4, 0.5),
+            Vec3::X,
+            radius,
+            10.,
+            &chunk_map,
+            |entity| query.get(&world, entity).unwrap().expand(),
+        )
+    };
+
+    // The ray passes just over the top of the wall, the sphere clips it
+    assert_eq!(None, cast(0.));
+    let hit = cast(0.6).unwrap();
+    assert!(hit.position.x < 0.5 && hit.position.y < 2.4);
+    assert_eq!(4, hit.material);
+}
+
+#[test]
+fn test_world_to_voxel() {
+    let origin = ChunkPosition::new(0, 0, 0);
+    assert_eq!(Some((origin, [9, 9, 9])), world_to_voxel(Vec3::ZERO));
+    assert_eq!(
+        Some((origin, [0, 19, 9])),
+        world_to_voxel(Vec3::new(-6.7, 7.4, 0.))
+    );
+    assert_eq!(
+        Some((ChunkPosition::new(-1, 1, 0), [19, 0, 9])),
+        world_to_voxel(Vec3::new(-7.5, 8., 0.))
+    );
+    assert_eq!(None, world_to_voxel(Vec3::splat(1e6)));
+    assert_eq!(None, world_to_voxel(Vec3::NAN));
+}
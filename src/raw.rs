@@ -1,6 +1,16 @@
+use std::collections::VecDeque;
+
+use bevy::prelude::{IVec3, Vec3};
 use fast_surface_nets::ndshape::{ConstShape3u32, Shape};
+use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
 
-use crate::{Voxel, CHUNK_BOUNDS, CHUNK_VOXELS};
+use crate::{
+    edit::{
+        apply_voxel, blend_aabb, sample_pos, valid_smoothness, voxel_bounds, Axis, Mode,
+        SignedDistanceFunction, SmoothMode,
+    },
+    Voxel, CHUNK_BOUNDS, CHUNK_VOXELS, VOXEL_SIZE,
+};
 
 /// RawChunk is the raw data of a chunk. This is not how chunks are stored, and is only kept in
 /// memory while it is being modified or used to create a chunk mesh
@@ -19,6 +29,18 @@ impl RawChunk {
         Self(vec![Voxel::AIR; CHUNK_VOXELS])
     }
 
+    /// Check if none of the voxels in the chunk are solid, stopping at the first solid voxel. A
+    /// chunk that is empty along with its neighbors has no mesh
+    pub fn is_empty(&self) -> bool {
+        !self.0.iter().any(|v| v.is_solid())
+    }
+
+    /// Check if every voxel in the chunk is solid, stopping at the first voxel that isn't. A chunk
+    /// that is full along with its neighbors has no mesh
+    pub fn is_full(&self) -> bool {
+        self.0.iter().all(|v| v.is_solid())
+    }
+
     /// Get the voxel at the specified coordinates
     pub fn get_voxel(&self, x: u32, y: u32, z: u32) -> Voxel {
         let idx = CHUNK_SHAPE.linearize([x, y, z]);
@@ -36,4 +58,594 @@ impl RawChunk {
         let idx = CHUNK_SHAPE.linearize([x, y, z]);
         self.0[idx as usize] = voxel;
     }
+
+    /// Get the voxel at the specified coordinates, or None if the coordinates lie outside of the
+    /// chunk
+    pub fn try_get_voxel(&self, x: u32, y: u32, z: u32) -> Option<Voxel> {
+        in_bounds(x, y, z).then(|| self.get_voxel(x, y, z))
+    }
+
+    /// Set the voxel at the specified coordinates to the given Voxel, or return an error if the
+    /// coordinates lie outside of the chunk
+    pub fn try_set_voxel(
+        &mut self,
+        x: u32,
+        y: u32,
+        z: u32,
+        voxel: Voxel,
+    ) -> Result<(), OutOfBounds> {
+        if !in_bounds(x, y, z) {
+            return Err(OutOfBounds {
+                position: [x, y, z],
+            });
+        }
+        self.set_voxel(x, y, z, voxel);
+        Ok(())
+    }
+
+    /// Replace the material of every voxel with the `from` material by the `to` material, keeping
+    /// the value of each voxel
+    pub fn replace_material(&mut self, from: u8, to: u8) {
+        for voxel in self.0.iter_mut().filter(|v| v.material() == from) {
+            *voxel = Voxel::new(to, voxel.value());
+        }
+    }
+
+    /// Get a copy of the chunk where only the voxels with the given material keep their value, and
+    /// every other voxel is air. Meshing the masked chunk gives the geometry of just that material,
+    /// for example to render transparent materials in a separate pass
+    pub fn mask_material(&self, material: u8) -> RawChunk {
+        RawChunk(
+            self.0
+                .iter()
+                .map(|v| {
+                    if v.material() == material {
+                        *v
+                    } else {
+                        Voxel::AIR
+                    }
+                })
+                .collect(),
+        )
+    }
+
+    /// Mirror the chunk along the axis, so the voxels at both ends of that axis swap places
+    pub fn mirror(&mut self, axis: Axis) {
+        let axis = axis as usize;
+        self.permute(|mut pos| {
+            pos[axis] = CHUNK_BOUNDS - 1 - pos[axis];
+            pos
+        });
+    }
+
+    /// Rotate the chunk around the vertical axis by a number of quarter turns, in the same
+    /// direction as a positive angle in [bevy::prelude::Quat::from_rotation_y]. A voxel on the +X
+    /// side of the chunk ends up on the -Z side
+    pub fn rotate_y(&mut self, quarter_turns: u8) {
+        for _ in 0..quarter_turns % 4 {
+            self.permute(|[x, y, z]| [z, y, CHUNK_BOUNDS - 1 - x]);
+        }
+    }
+
+    /// Move every voxel to the position returned by target
+    fn permute(&mut self, target: impl Fn([u32; 3]) -> [u32; 3]) {
+        let mut voxels = vec![Voxel::AIR; CHUNK_VOXELS];
+        for (idx, voxel) in self.0.iter().enumerate() {
+            let pos = target(CHUNK_SHAPE.delinearize(idx as u32));
+            voxels[CHUNK_SHAPE.linearize(pos) as usize] = *voxel;
+        }
+        self.0 = voxels;
+    }
+
+    /// Iterate over the vertical column of voxels at the specified x and z coordinates, from
+    /// bottom to top. Voxels are stored with x as the fastest changing axis, followed by y and then
+    /// z, so columns are not contiguous in memory
+    pub fn column_iter(&self, x: u32, z: u32) -> impl Iterator<Item = Voxel> + '_ {
+        let start = CHUNK_SHAPE.linearize([x, 0, z]) as usize;
+        self.0[start..]
+            .iter()
+            .step_by(CHUNK_BOUNDS as usize)
+            .take(CHUNK_BOUNDS as usize)
+            .copied()
+    }
+
+    /// Set the vertical column of voxels at the specified x and z coordinates, starting from the
+    /// bottom. Voxels beyond the height of the chunk are ignored
+    pub fn fill_column(&mut self, x: u32, z: u32, voxels: &[Voxel]) {
+        let start = CHUNK_SHAPE.linearize([x, 0, z]) as usize;
+        for (voxel, new) in self.0[start..]
+            .iter_mut()
+            .step_by(CHUNK_BOUNDS as usize)
+            .zip(voxels.iter())
+        {
+            *voxel = *new;
+        }
+    }
+
+    /// Replace all voxels matching the predicate that are connected to the seed voxel trough their
+    /// 6 direct neighbors. The fill stops at the chunk boundary
+    pub fn flood_fill(
+        &mut self,
+        seed: [u32; 3],
+        predicate: impl Fn(Voxel) -> bool,
+        replacement: Voxel,
+    ) {
+        let mut visited = vec![false; CHUNK_VOXELS];
+        let mut queue = VecDeque::new();
+        queue.push_back(seed);
+
+        while let Some([x, y, z]) = queue.pop_front() {
+            let idx = CHUNK_SHAPE.linearize([x, y, z]) as usize;
+            if visited[idx] || !predicate(self.0[idx]) {
+                continue;
+            }
+            visited[idx] = true;
+            self.0[idx] = replacement;
+
+            if x > 0 {
+                queue.push_back([x - 1, y, z]);
+            }
+            if x < CHUNK_BOUNDS - 1 {
+                queue.push_back([x + 1, y, z]);
+            }
+            if y > 0 {
+                queue.push_back([x, y - 1, z]);
+            }
+            if y < CHUNK_BOUNDS - 1 {
+                queue.push_back([x, y + 1, z]);
+            }
+            if z > 0 {
+                queue.push_back([x, y, z - 1]);
+            }
+            if z < CHUNK_BOUNDS - 1 {
+                queue.push_back([x, y, z + 1]);
+            }
+        }
+    }
+
+    /// Fill every column of the chunk solid up to the height returned by `height_at` for its x and
+    /// z coordinates. Heights are in voxel units, and `chunk_origin_y` is the height of the bottom
+    /// voxel of the chunk in the same units. The voxels around the height get the distance to it,
+    /// so the surface lies exactly at the height. Like adding a shape, voxels that are already more
+    /// solid keep their value and material
+    pub fn fill_below_heightmap(
+        &mut self,
+        height_at: impl Fn(u32, u32) -> f32,
+        material: u8,
+        chunk_origin_y: f32,
+    ) {
+        for z in 0..CHUNK_BOUNDS {
+            for x in 0..CHUNK_BOUNDS {
+                let height = height_at(x, z) - chunk_origin_y;
+                for y in 0..CHUNK_BOUNDS {
+                    let distance = (y as f32 - height) * VOXEL_SIZE;
+                    let voxel = self.get_mut_voxel(x, y, z);
+                    if distance < f32::from(*voxel) {
+                        *voxel = Voxel::new(material, 0).with_value_f32(distance.clamp(-1., 1.));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Apply a [SignedDistanceFunction] to just this chunk, at the specified position relative to
+    /// the chunk. This blends the shape the same way as [crate::edit::ChunkModifier::apply_sdf]
+    /// with the default [SmoothMode], but voxels outside of the chunk are ignored. Returns true if
+    /// any stored voxel changed
+    pub fn apply_sdf_local(
+        &mut self,
+        sdf: impl SignedDistanceFunction,
+        mode: Mode,
+        material: u8,
+        smoothness: f32,
+        local_pos: Vec3,
+    ) -> bool {
+        if !valid_smoothness(smoothness) || !local_pos.is_finite() {
+            return false;
+        }
+
+        let aabb = blend_aabb(sdf.aabb(), smoothness, SmoothMode::default());
+        let (min, max) = voxel_bounds(aabb, local_pos);
+        let (min, max) = (
+            min.max(IVec3::ZERO),
+            max.min(IVec3::splat(CHUNK_BOUNDS as i32)),
+        );
+        let mut changed = false;
+        for z in min.z..max.z {
+            for y in min.y..max.y {
+                for x in min.x..max.x {
+                    let distance = sdf.sdf(sample_pos(x, y, z, local_pos));
+                    changed |= apply_voxel(
+                        self.get_mut_voxel(x as u32, y as u32, z as u32),
+                        distance,
+                        mode,
+                        material,
+                        smoothness,
+                        SmoothMode::default(),
+                    );
+                }
+            }
+        }
+        changed
+    }
+
+    /// Iterate over all solid voxels that have an empty voxel directly above them, along with their
+    /// coordinates. Voxels in the top layer are skipped, since the voxel above them is in another
+    /// chunk
+    pub fn surface_voxels(&self) -> impl Iterator<Item = ([u32; 3], Voxel)> + '_ {
+        self.0.iter().enumerate().filter_map(move |(idx, voxel)| {
+            let [x, y, z] = CHUNK_SHAPE.delinearize(idx as u32);
+            (y < CHUNK_BOUNDS - 1 && voxel.is_solid() && !self.get_voxel(x, y + 1, z).is_solid())
+                .then_some(([x, y, z], *voxel))
+        })
+    }
+
+    /// Get the surface normal at the specified voxel, calculated from the gradient of the signed
+    /// distance values of the neighboring voxels. At the edges of the chunk the voxel itself is
+    /// used in place of the missing neighbor. If the gradient is zero, [Vec3::Y] is returned
+    pub fn voxel_normal(&self, x: u32, y: u32, z: u32) -> Vec3 {
+        let sample = |[x, y, z]: [u32; 3]| f32::from(self.get_voxel(x, y, z));
+        let mut gradient = Vec3::ZERO;
+        for axis in 0..3 {
+            let (mut low, mut high) = ([x, y, z], [x, y, z]);
+            low[axis] = low[axis].saturating_sub(1);
+            high[axis] = (high[axis] + 1).min(CHUNK_BOUNDS - 1);
+            gradient[axis] = (sample(high) - sample(low)) / (high[axis] - low[axis]) as f32;
+        }
+        gradient.try_normalize().unwrap_or(Vec3::Y)
+    }
+
+    /// Get the signed distance value of every voxel in storage order, the same values
+    /// [crate::surface_nets::generate_chunk] meshes. This is mostly useful for debugging
+    pub fn sdf_grid(&self) -> Vec<f32> {
+        self.0.iter().map(|v| f32::from(*v)).collect()
+    }
+
+    /// Merge another chunk into this one. With [Mode::Add] the most solid voxel is kept, along
+    /// with its material. With [Mode::Remove] the other chunk is carved out of this one. With
+    /// [Mode::Clear] every voxel where the other chunk is solid becomes air
+    pub fn merge(&mut self, other: &RawChunk, mode: Mode) {
+        for (voxel, other) in self.0.iter_mut().zip(other.0.iter()) {
+            match mode {
+                Mode::Add => {
+                    if other.value() > voxel.value() {
+                        *voxel = *other;
+                    }
+                }
+                Mode::Remove => {
+                    let value = voxel.value().min(Voxel::MAX_VALUE - other.value());
+                    *voxel = Voxel::new(voxel.material(), value);
+                }
+                Mode::Clear => {
+                    if other.is_solid() {
+                        *voxel = Voxel::AIR;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Check if the coordinates lie within a chunk
+pub(crate) fn in_bounds(x: u32, y: u32, z: u32) -> bool {
+    x < CHUNK_BOUNDS && y < CHUNK_BOUNDS && z < CHUNK_BOUNDS
+}
+
+/// The error returned when accessing a voxel outside of the chunk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutOfBounds {
+    /// The coordinates that were accessed
+    pub position: [u32; 3],
+}
+
+impl std::fmt::Display for OutOfBounds {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "voxel {:?} lies outside of the chunk, which has {} voxels per side",
+            self.position, CHUNK_BOUNDS
+        )
+    }
+}
+
+impl std::error::Error for OutOfBounds {}
+
+impl Serialize for RawChunk {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.0.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for RawChunk {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let voxels = Vec::<Voxel>::deserialize(deserializer)?;
+        if voxels.len() != CHUNK_VOXELS {
+            return Err(D::Error::invalid_length(
+                voxels.len(),
+                &"the number of voxels in a chunk",
+            ));
+        }
+        Ok(Self(voxels))
+    }
+}
+
+#[test]
+fn test_serde_roundtrip() {
+    let mut chunk = RawChunk::air();
+    chunk.set_voxel(1, 2, 3, Voxel::new(4, 500));
+    chunk.set_voxel(
+        19,
+        19,
+        19,
+        Voxel::new(Voxel::MAX_MATERIAL, Voxel::MAX_VALUE),
+    );
+
+    let json = serde_json::to_string(&chunk).unwrap();
+    let output: RawChunk = serde_json::from_str(&json).unwrap();
+    assert_eq!(chunk.0, output.0);
+
+    assert!(serde_json::from_str::<RawChunk>("[0, 1, 2]").is_err());
+}
+
+#[test]
+fn test_empty_and_full() {
+    let air = RawChunk::air();
+    assert!(air.is_empty());
+    assert!(!air.is_full());
+
+    let mut solid = RawChunk(vec![Voxel::new(1, Voxel::MAX_VALUE); CHUNK_VOXELS]);
+    assert!(solid.is_full());
+    assert!(!solid.is_empty());
+
+    solid.set_voxel(4, 5, 6, Voxel::AIR);
+    assert!(!solid.is_full());
+    assert!(!solid.is_empty());
+}
+
+#[test]
+fn test_column() {
+    let column: Vec<_> = (0..CHUNK_BOUNDS)
+        .map(|y| Voxel::new(y as u8, y as u16 * 10))
+        .collect();
+
+    let mut chunk = RawChunk::air();
+    chunk.fill_column(4, 7, &column);
+
+    assert_eq!(column, chunk.column_iter(4, 7).collect::<Vec<_>>());
+    for y in 0..CHUNK_BOUNDS {
+        assert_eq!(column[y as usize], chunk.get_voxel(4, y, 7));
+    }
+    assert!(chunk.column_iter(5, 7).all(|v| v == Voxel::AIR));
+}
+
+#[test]
+fn test_flood_fill() {
+    let solid = Voxel::new(1, Voxel::MAX_VALUE);
+    let water = Voxel::new(2, 0);
+    let mut chunk = RawChunk(vec![solid; CHUNK_VOXELS]);
+    // A 2x2x2 air pocket, and a single separate air voxel
+    for x in 5..7 {
+        for y in 5..7 {
+            for z in 5..7 {
+                chunk.set_voxel(x, y, z, Voxel::AIR);
+            }
+        }
+    }
+    chunk.set_voxel(10, 10, 10, Voxel::AIR);
+
+    chunk.flood_fill([5, 5, 5], |v| v == Voxel::AIR, water);
+
+    assert_eq!(8, chunk.0.iter().filter(|v| **v == water).count());
+    assert_eq!(water, chunk.get_voxel(6, 6, 6));
+    assert_eq!(Voxel::AIR, chunk.get_voxel(10, 10, 10));
+    assert_eq!(
+        CHUNK_VOXELS - 9,
+        chunk.0.iter().filter(|v| **v == solid).count()
+    );
+}
+
+#[test]
+fn test_fill_below_heightmap() {
+    let mut chunk = RawChunk::air();
+    chunk.fill_below_heightmap(|_, _| 30.3, 2, 20.);
+
+    for x in 0..CHUNK_BOUNDS {
+        for z in 0..CHUNK_BOUNDS {
+            let column: Vec<_> = chunk.column_iter(x, z).collect();
+            assert!(column[..=10]
+                .iter()
+                .all(|v| v.is_solid() && v.material() == 2));
+            assert!(column[11..].iter().all(|v| !v.is_solid()));
+            assert_eq!(Voxel::new(2, Voxel::MAX_VALUE), column[0]);
+            // The voxels on both sides of the surface hold the distance to it
+            assert!(column[10].value() < Voxel::MAX_VALUE);
+            assert!((f32::from(column[10]) + 0.3 * VOXEL_SIZE).abs() < 0.01);
+            assert!((f32::from(column[11]) - 0.7 * VOXEL_SIZE).abs() < 0.01);
+        }
+    }
+}
+
+#[test]
+fn test_apply_sdf_local() {
+    use crate::{
+        edit::{ChunkModifier, SphereSdf},
+        ChunkMap, ChunkPosition,
+    };
+
+    let mut chunk = RawChunk::air();
+    assert!(chunk.apply_sdf_local(SphereSdf(2.), Mode::Add, 3, 0.5, Vec3::new(7., 6., 8.)));
+
+    let mut modifier = ChunkModifier::default();
+    modifier.apply_sdf(
+        ChunkPosition::new(0, 0, 0),
+        &mut ChunkMap::default(),
+        |_| unreachable!(),
+        SphereSdf(2.),
+        Mode::Add,
+        3,
+        0.5,
+        Vec3::new(7., 6., 8.),
+    );
+    let expected = modifier
+        .modified_chunk(ChunkPosition::new(0, 0, 0))
+        .unwrap();
+    assert_eq!(expected.0, chunk.0);
+}
+
+#[test]
+fn test_surface_voxels() {
+    let mut chunk = RawChunk::air();
+    for x in 0..CHUNK_BOUNDS {
+        for y in 0..CHUNK_BOUNDS / 2 {
+            for z in 0..CHUNK_BOUNDS {
+                chunk.set_voxel(x, y, z, Voxel::new(1, Voxel::MAX_VALUE));
+            }
+        }
+    }
+
+    let surface: Vec<_> = chunk.surface_voxels().collect();
+    assert_eq!((CHUNK_BOUNDS * CHUNK_BOUNDS) as usize, surface.len());
+    assert!(surface
+        .iter()
+        .all(|([_, y, _], voxel)| *y == CHUNK_BOUNDS / 2 - 1 && voxel.material() == 1));
+}
+
+#[test]
+fn test_voxel_normal() {
+    let mut chunk = RawChunk::air();
+    for x in 0..CHUNK_BOUNDS {
+        for y in 0..CHUNK_BOUNDS / 2 {
+            for z in 0..CHUNK_BOUNDS {
+                chunk.set_voxel(x, y, z, Voxel::new(1, Voxel::MAX_VALUE));
+            }
+        }
+    }
+
+    for (x, y, z) in [(5, 9, 5), (0, 10, 0), (19, 9, 7)] {
+        let normal = chunk.voxel_normal(x, y, z);
+        assert!(normal.dot(Vec3::Y) > 0.99, "{normal} at {x}, {y}, {z}");
+    }
+    assert_eq!(Vec3::Y, RawChunk::air().voxel_normal(0, 0, 0));
+}
+
+#[test]
+fn test_sdf_grid() {
+    let air = RawChunk::air().sdf_grid();
+    assert_eq!(CHUNK_VOXELS, air.len());
+    assert!(air.iter().all(|v| *v == 1.));
+
+    let solid = RawChunk(vec![Voxel::new(0, Voxel::MAX_VALUE); CHUNK_VOXELS]).sdf_grid();
+    assert_eq!(CHUNK_VOXELS, solid.len());
+    assert!(solid.iter().all(|v| *v == -1.));
+}
+
+#[test]
+fn test_merge_add() {
+    let solid = RawChunk(vec![Voxel::new(3, Voxel::MAX_VALUE); CHUNK_VOXELS]);
+    let mut chunk = RawChunk::air();
+    chunk.merge(&solid, Mode::Add);
+    assert_eq!(solid.0, chunk.0);
+}
+
+#[test]
+fn test_merge_remove() {
+    let solid = RawChunk(vec![Voxel::new(3, Voxel::MAX_VALUE); CHUNK_VOXELS]);
+    let mut chunk = RawChunk(vec![Voxel::new(1, Voxel::MAX_VALUE); CHUNK_VOXELS]);
+    chunk.merge(&solid, Mode::Remove);
+    assert!(chunk.0.iter().all(|v| *v == Voxel::new(1, 0)));
+}
+
+#[test]
+fn test_out_of_bounds() {
+    let mut chunk = RawChunk::air();
+    let edge = crate::CHUNK_SIDES as u32;
+    assert_eq!(Some(Voxel::AIR), chunk.try_get_voxel(edge - 1, 0, 0));
+    assert_eq!(None, chunk.try_get_voxel(edge, 0, 0));
+    assert_eq!(None, chunk.try_get_voxel(0, 0, edge));
+
+    let solid = Voxel::new(1, Voxel::MAX_VALUE);
+    assert_eq!(Ok(()), chunk.try_set_voxel(0, edge - 1, 0, solid));
+    assert_eq!(solid, chunk.get_voxel(0, edge - 1, 0));
+    assert_eq!(
+        Err(OutOfBounds {
+            position: [0, edge, 0]
+        }),
+        chunk.try_set_voxel(0, edge, 0, solid)
+    );
+}
+
+#[test]
+fn test_replace_material() {
+    let mut chunk = RawChunk::air();
+    chunk.set_voxel(1, 2, 3, Voxel::new(1, 700));
+    chunk.set_voxel(4, 5, 6, Voxel::new(2, 600));
+    chunk.set_voxel(7, 8, 9, Voxel::new(2, 0));
+    chunk.replace_material(2, 5);
+
+    assert_eq!(Voxel::new(1, 700), chunk.get_voxel(1, 2, 3));
+    assert_eq!(Voxel::new(5, 600), chunk.get_voxel(4, 5, 6));
+    assert_eq!(Voxel::new(5, 0), chunk.get_voxel(7, 8, 9));
+    assert_eq!(Voxel::AIR, chunk.get_voxel(0, 0, 0));
+}
+
+#[test]
+fn test_mask_material() {
+    let mut chunk = RawChunk::air();
+    for x in 0..CHUNK_BOUNDS {
+        for y in 0..CHUNK_BOUNDS / 2 {
+            let material = if x < CHUNK_BOUNDS / 2 { 1 } else { 2 };
+            for z in 0..CHUNK_BOUNDS {
+                chunk.set_voxel(x, y, z, Voxel::new(material, Voxel::MAX_VALUE));
+            }
+        }
+    }
+
+    for material in [1, 2] {
+        let masked = chunk.mask_material(material);
+        for (voxel, masked) in chunk.0.iter().zip(masked.0.iter()) {
+            assert_eq!(
+                masked.is_solid(),
+                voxel.is_solid() && voxel.material() == material
+            );
+            assert!(!masked.is_solid() || masked.material() == material);
+        }
+    }
+    assert_eq!(RawChunk::air().0, chunk.mask_material(3).0);
+}
+
+#[test]
+fn test_merge_clear() {
+    let mut other = RawChunk::air();
+    other.set_voxel(1, 1, 1, Voxel::new(3, Voxel::MAX_VALUE));
+    let mut chunk = RawChunk(vec![Voxel::new(1, Voxel::MAX_VALUE); CHUNK_VOXELS]);
+    chunk.merge(&other, Mode::Clear);
+    assert_eq!(Voxel::AIR, chunk.get_voxel(1, 1, 1));
+    assert_eq!(Voxel::new(1, Voxel::MAX_VALUE), chunk.get_voxel(1, 1, 2));
+}
+
+#[test]
+fn test_mirror_and_rotate() {
+    let mut chunk = RawChunk::air();
+    for (i, pos) in [[0, 0, 0], [19, 3, 5], [2, 19, 7], [4, 8, 19]]
+        .iter()
+        .enumerate()
+    {
+        chunk.set_voxel(pos[0], pos[1], pos[2], Voxel::new(i as u8 + 1, 500));
+    }
+    let original = chunk.0.clone();
+
+    for axis in [Axis::X, Axis::Y, Axis::Z] {
+        chunk.mirror(axis);
+        assert_ne!(original, chunk.0);
+        chunk.mirror(axis);
+        assert_eq!(original, chunk.0);
+    }
+
+    chunk.rotate_y(1);
+    // +X ends up on -Z
+    assert_eq!(Voxel::new(2, 500), chunk.get_voxel(5, 3, 0));
+    chunk.rotate_y(3);
+    assert_eq!(original, chunk.0);
+    chunk.rotate_y(4);
+    assert_eq!(original, chunk.0);
 }
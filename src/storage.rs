@@ -1,16 +1,108 @@
-use crate::{RawChunk, Voxel, CHUNK_SIZE, CHUNK_VOXELS};
+use crate::{
+    raw::{in_bounds, CHUNK_SHAPE},
+    RawChunk, Shape, Voxel, CHUNK_SIZE, CHUNK_VOXELS, WORLD_MAX_CHUNK, WORLD_MIN_CHUNK,
+};
 
-use bevy::{prelude::*, utils::HashMap};
-use serde::{Deserialize, Serialize};
+use std::{
+    hash::{Hash, Hasher},
+    sync::Arc,
+};
+
+use bevy::{
+    prelude::*,
+    utils::{HashMap, HashSet},
+};
+use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
 use smallvec::SmallVec;
 
-/// The resource that stores the entity of every existing chunk, indexed by chunk position
+/// The resource that stores the entity of every existing chunk, indexed by chunk position.
+/// Positions that are known to only hold air can be marked with [ChunkMap::mark_air] instead, so
+/// they don't need an entity. Meshing and edits treat them the same as missing chunks, which are
+/// always air
 #[derive(Resource, Deref, DerefMut)]
-pub struct ChunkMap(HashMap<ChunkPosition, Entity>);
+pub struct ChunkMap {
+    #[deref]
+    chunks: HashMap<ChunkPosition, Entity>,
+    air: HashSet<ChunkPosition>,
+}
 
 impl Default for ChunkMap {
     fn default() -> Self {
-        Self(HashMap::with_capacity(500))
+        Self::with_capacity(500)
+    }
+}
+
+/// What is known about the chunk at a position, see [ChunkMap::slot]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChunkSlot {
+    /// The chunk only holds air and has no entity
+    Air,
+    /// The entity of the chunk
+    Entity(Entity),
+}
+
+impl ChunkMap {
+    /// Create a chunk map with room for `capacity` chunk entities before it needs to grow. The
+    /// default has room for 500 chunks
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            chunks: HashMap::with_capacity(capacity),
+            air: HashSet::default(),
+        }
+    }
+
+    /// Get the number of chunk entities in the map, along with the number it can hold before it
+    /// needs to grow. Positions marked as air aren't counted
+    pub fn stats(&self) -> (usize, usize) {
+        (self.chunks.len(), self.chunks.capacity())
+    }
+
+    /// Get the entity for the chunk at the specified position. If the chunk does not exist yet, an
+    /// empty air chunk is spawned and added to the map
+    pub fn get_or_spawn(&mut self, pos: ChunkPosition, commands: &mut Commands) -> Entity {
+        self.air.remove(&pos);
+        *self
+            .chunks
+            .entry(pos)
+            .or_insert_with(|| commands.spawn((pos, ChunkData::air())).id())
+    }
+
+    /// Mark the position as only holding air, without spawning an entity for it. A chunk entity
+    /// inserted at the same position later takes precedence
+    pub fn mark_air(&mut self, pos: ChunkPosition) {
+        self.air.insert(pos);
+    }
+
+    /// Remove the air mark from the position, returning true if it was marked
+    pub fn unmark_air(&mut self, pos: ChunkPosition) -> bool {
+        self.air.remove(&pos)
+    }
+
+    /// Get the entity of the chunk at the position, or [ChunkSlot::Air] if the position is marked
+    /// as air. Returns None when nothing is known about the position
+    pub fn slot(&self, pos: ChunkPosition) -> Option<ChunkSlot> {
+        match self.chunks.get(&pos) {
+            Some(entity) => Some(ChunkSlot::Entity(*entity)),
+            None => self.air.contains(&pos).then_some(ChunkSlot::Air),
+        }
+    }
+
+    /// Iterate over all existing chunks in the box between min and max, inclusive
+    pub fn iter_region(
+        &self,
+        min: ChunkPosition,
+        max: ChunkPosition,
+    ) -> impl Iterator<Item = (ChunkPosition, Entity)> + '_ {
+        (min.0[0]..=max.0[0])
+            .flat_map(move |x| (min.0[1]..=max.0[1]).map(move |y| (x, y)))
+            .flat_map(move |(x, y)| (min.0[2]..=max.0[2]).map(move |z| ChunkPosition([x, y, z])))
+            .filter_map(move |pos| self.chunks.get(&pos).map(|entity| (pos, *entity)))
+    }
+
+    /// Iterate over the world-space bounding box of every loaded chunk as a (min, max) pair, see
+    /// [ChunkPosition::aabb]. This is useful to draw chunk boundaries with gizmos while debugging
+    pub fn chunk_wireframes(&self) -> impl Iterator<Item = (Vec3, Vec3)> + '_ {
+        self.chunks.keys().map(ChunkPosition::aabb)
     }
 }
 
@@ -55,6 +147,21 @@ impl ChunkPosition {
         Self([x, y, z])
     }
 
+    /// Check if the chunk coordinates are within the bounds of the world, which are
+    /// [WORLD_MIN_CHUNK] and [WORLD_MAX_CHUNK] on every axis
+    pub fn is_valid(x: i32, y: i32, z: i32) -> bool {
+        let range = WORLD_MIN_CHUNK..=WORLD_MAX_CHUNK;
+        range.contains(&x) && range.contains(&y) && range.contains(&z)
+    }
+
+    /// Get the distance to another chunk along the axis where they are furthest apart
+    pub fn chebyshev_distance(&self, other: &ChunkPosition) -> u32 {
+        (0..3)
+            .map(|i| (self.0[i] as i32 - other.0[i] as i32).unsigned_abs())
+            .max()
+            .unwrap_or(0)
+    }
+
     /// Get the desired Transform translation for this chunk
     pub fn get_translation(&self) -> Vec3 {
         Vec3::new(
@@ -64,9 +171,19 @@ impl ChunkPosition {
         )
     }
 
+    /// Get the world-space bounding box of this chunk as a (min, max) pair. Like the chunk meshes,
+    /// which are offset by `-CHUNK_SIZE / 2`, the box is centered on [Self::get_translation]
+    pub fn aabb(&self) -> (Vec3, Vec3) {
+        let center = self.get_translation();
+        (
+            center - Vec3::splat(CHUNK_SIZE / 2.),
+            center + Vec3::splat(CHUNK_SIZE / 2.),
+        )
+    }
+
     /// Get the ChunkPos for this translation
     pub fn from_translation(pos: &Vec3) -> Option<Self> {
-        let range = (i8::MIN as f32 * CHUNK_SIZE)..(i8::MAX as f32 * CHUNK_SIZE);
+        let range = (WORLD_MIN_CHUNK as f32 * CHUNK_SIZE)..(WORLD_MAX_CHUNK as f32 * CHUNK_SIZE);
         if !range.contains(&pos.x) || !range.contains(&pos.y) || !range.contains(&pos.z) {
             return None;
         }
@@ -77,6 +194,15 @@ impl ChunkPosition {
         ]))
     }
 
+    /// Get the chunk an object with this transform is in, along with its position relative to that
+    /// chunk as used by [crate::edit::ChunkModifier::apply_sdf]. The chunk meshes are offset by
+    /// `-CHUNK_SIZE / 2`, which is accounted for so edits land where the object visually is
+    pub fn from_transform(transform: &Transform) -> Option<(Self, Vec3)> {
+        let chunk = Self::from_translation(&transform.translation)?;
+        let relative = transform.translation - chunk.get_translation() + CHUNK_SIZE / 2.;
+        Some((chunk, relative))
+    }
+
     /// Create a ChunkPosition from big endian bytes
     pub fn from_be_bytes(bytes: [u8; 3]) -> Self {
         Self([
@@ -96,9 +222,313 @@ impl ChunkPosition {
     }
 }
 
+/// A bitmask with one bit per voxel in a chunk, see [ChunkData::occupancy_mask]
+pub type OccupancyMask = [u64; CHUNK_VOXELS.div_ceil(64)];
+
+/// Get all chunk positions within radius chunks of the center, ordered by their
+/// [ChunkPosition::chebyshev_distance] to the center so the nearest chunks come first. Chunks at
+/// the same distance are ordered from closest to furthest in a straight line, so the corners come
+/// last. Positions outside of the world are skipped
+pub fn chunk_load_order(center: ChunkPosition, radius: u8) -> Vec<ChunkPosition> {
+    let radius = radius as i32;
+    let range = |axis: usize| {
+        let c = center.0[axis] as i32;
+        (c - radius).max(WORLD_MIN_CHUNK)..=(c + radius).min(WORLD_MAX_CHUNK)
+    };
+
+    let mut positions = Vec::new();
+    for x in range(0) {
+        for y in range(1) {
+            for z in range(2) {
+                positions.push(ChunkPosition([x as i8, y as i8, z as i8]));
+            }
+        }
+    }
+    positions.sort_by_key(|pos| {
+        let squared: i32 = (0..3)
+            .map(|i| (pos.0[i] as i32 - center.0[i] as i32).pow(2))
+            .sum();
+        (pos.chebyshev_distance(&center), squared)
+    });
+    positions
+}
+
+/// Get the positions of all chunks that overlap the world-space box between min and max, for
+/// example to find the chunks an explosion can affect. Like [ChunkPosition::aabb], chunks are
+/// centered on their translation. Positions outside of the world are skipped
+pub fn chunks_in_aabb(min: Vec3, max: Vec3) -> Vec<ChunkPosition> {
+    if !min.is_finite() || !max.is_finite() {
+        return Vec::new();
+    }
+    let chunk = |v: f32| ((v + CHUNK_SIZE / 2.) / CHUNK_SIZE).floor();
+    let range = |min: f32, max: f32| {
+        (chunk(min).max(WORLD_MIN_CHUNK as f32) as i32)
+            ..=(chunk(max).min(WORLD_MAX_CHUNK as f32) as i32)
+    };
+
+    let mut positions = Vec::new();
+    for x in range(min.x, max.x) {
+        for y in range(min.y, max.y) {
+            for z in range(min.z, max.z) {
+                positions.push(ChunkPosition([x as i8, y as i8, z as i8]));
+            }
+        }
+    }
+    positions
+}
+
+/// Encode a list of chunk positions compactly, for example to send the chunks that changed over
+/// the network. The positions are sorted and deduplicated, then each is stored as the difference
+/// to the previous one in a varint. Nearby positions have small differences, so a cluster of chunks
+/// takes one or two bytes per position. See [decode_positions]
+pub fn encode_positions(positions: &[ChunkPosition]) -> Vec<u8> {
+    let mut keys: Vec<u32> = positions.iter().map(|pos| position_key(*pos)).collect();
+    keys.sort_unstable();
+    keys.dedup();
+
+    let mut bytes = Vec::with_capacity(keys.len() * 2);
+    let mut previous = 0;
+    for key in keys {
+        let mut delta = key - previous;
+        previous = key;
+        while delta >= 0x80 {
+            bytes.push(delta as u8 | 0x80);
+            delta >>= 7;
+        }
+        bytes.push(delta as u8);
+    }
+    bytes
+}
+
+/// Decode chunk positions encoded with [encode_positions], which returns them sorted. Returns None
+/// if the bytes end in the middle of a position or describe a position that can't exist
+pub fn decode_positions(bytes: &[u8]) -> Option<Vec<ChunkPosition>> {
+    let mut positions = Vec::new();
+    let mut key = 0u32;
+    let mut delta = 0u32;
+    let mut shift = 0;
+    for &byte in bytes {
+        if shift > 21 {
+            return None;
+        }
+        delta |= ((byte & 0x7f) as u32) << shift;
+        shift += 7;
+        if byte & 0x80 != 0 {
+            continue;
+        }
+
+        key = key.checked_add(delta).filter(|key| *key <= 0xff_ffff)?;
+        let [_, x, y, z] = key.to_be_bytes();
+        positions.push(ChunkPosition(
+            [x, y, z].map(|v| (v as i16 + i8::MIN as i16) as i8),
+        ));
+        delta = 0;
+        shift = 0;
+    }
+    (shift == 0).then_some(positions)
+}
+
+// Pack a position into a single number that sorts the same way as the position
+fn position_key(pos: ChunkPosition) -> u32 {
+    let [x, y, z] = pos.0.map(|v| (v as i16 - i8::MIN as i16) as u8);
+    u32::from_be_bytes([0, x, y, z])
+}
+
 /// ChunkData stores data for a chunk with Run Lenght Encoding compression.
-#[derive(Component, Clone, Debug, Serialize, Deserialize)]
-pub struct ChunkData(SmallVec<[u16; 3]>);
+#[derive(Component, Clone, Debug)]
+pub struct ChunkData(Encoding);
+
+/// ChunkData is always serialized as its voxel runs, see [ChunkData::from_rle], so chunks saved
+/// before chunks could be stored as materials still load. Loaded chunks are stored as materials
+/// again when that is smaller
+impl Serialize for ChunkData {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if let Encoding::Voxels(buf) = &self.0 {
+            return serializer.serialize_newtype_struct("ChunkData", buf);
+        }
+        let mut encoder = Encoder::default();
+        for (voxel, n) in self.encoded_runs() {
+            encoder.push(voxel, n);
+        }
+        encoder.flush();
+        serializer.serialize_newtype_struct("ChunkData", &encoder.buf)
+    }
+}
+
+impl<'de> Deserialize<'de> for ChunkData {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(rename = "ChunkData")]
+        struct Runs(SmallVec<[u16; INLINE_VOXELS]>);
+
+        let Runs(buf) = Runs::deserialize(deserializer)?;
+        let data = ChunkData::from_rle(buf).map_err(D::Error::custom)?;
+        let mut encoder = Encoder::default();
+        for (voxel, n) in data.encoded_runs() {
+            encoder.push(voxel, n);
+        }
+        Ok(encoder.finish())
+    }
+}
+
+/// The number of u16s chunks stored as voxel runs keep inline before they allocate. The
+/// default fits a chunk with a single run, like an air chunk. Worlds where most chunks have a few
+/// more runs can raise it with the `inline-16` feature. This stays internal, so enabling the
+/// feature doesn't change any public types
+const INLINE_VOXELS: usize = if cfg!(feature = "inline-16") { 16 } else { 3 };
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Encoding {
+    /// Run Length Encoded voxels, see [Encoder]
+    Voxels(SmallVec<[u16; INLINE_VOXELS]>),
+    /// Run Length Encoded materials, for chunks where every voxel has the same value. A single
+    /// material is stored as is, and a run is stored as the material with [MATERIAL_RUN] set
+    /// followed by the length of the run. Runs longer than [u8::MAX] are split
+    Materials {
+        value: u16,
+        materials: SmallVec<[u8; 6]>,
+    },
+}
+
+/// Chunks are equal when they hold the same voxels, see [ChunkData::content_eq]
+impl PartialEq for ChunkData {
+    fn eq(&self, other: &Self) -> bool {
+        self.content_eq(other)
+    }
+}
+
+impl Eq for ChunkData {}
+
+/// Like equality, the hash only depends on the voxels and not on how they are encoded
+impl Hash for ChunkData {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        let mut runs = self.encoded_runs().filter(|(_, n)| *n > 0).peekable();
+        while let Some((voxel, mut n)) = runs.next() {
+            while let Some((_, more)) = runs.next_if(|(next, _)| *next == voxel) {
+                n += more;
+            }
+            voxel.raw().hash(state);
+            n.hash(state);
+        }
+    }
+}
+
+/// A system that despawns chunks without any solid voxels after they changed, and marks their
+/// position as air in the [ChunkMap] instead, see [ChunkMap::mark_air]. This frees the memory and
+/// meshing time of chunks that were dug out completely, see [ChunkData::is_empty]. Neighboring
+/// chunks don't need them for their seams, since meshing treats missing chunks as air anyway
+pub fn prune_empty_chunks(
+    mut commands: Commands,
+    mut chunk_map: ResMut<ChunkMap>,
+    chunks: Query<(Entity, &ChunkPosition, &ChunkData), Changed<ChunkData>>,
+) {
+    for (entity, pos, data) in chunks.iter() {
+        if !data.is_empty() {
+            continue;
+        }
+        if chunk_map.get(pos) == Some(&entity) {
+            chunk_map.remove(pos);
+            chunk_map.mark_air(*pos);
+        }
+        commands.entity(entity).despawn();
+    }
+}
+
+/// Get a bitset with bit `m` set for every material `m` in the existing chunks in the box between
+/// min and max, inclusive. See [ChunkData::material_mask]. Missing chunks are skipped, so they
+/// don't add air
+pub fn materials_in_region(
+    min: ChunkPosition,
+    max: ChunkPosition,
+    chunk_map: &ChunkMap,
+    query: &Query<&ChunkData>,
+) -> u64 {
+    chunk_map
+        .iter_region(min, max)
+        .filter_map(|(_, entity)| query.get(entity).ok())
+        .fold(0, |mask, data| mask | data.material_mask())
+}
+
+/// Compress a batch of chunks, returning the chunk data in the same order. With the `rayon` feature
+/// the chunks are compressed in parallel
+pub fn compress_chunks(chunks: &[RawChunk]) -> Vec<ChunkData> {
+    #[cfg(feature = "rayon")]
+    {
+        use rayon::prelude::*;
+        chunks.par_iter().map(ChunkData::from).collect()
+    }
+    #[cfg(not(feature = "rayon"))]
+    {
+        chunks.iter().map(ChunkData::from).collect()
+    }
+}
+
+/// A FNV-1a hasher. Unlike the hashers from std, the hashes it produces are the same across runs
+/// and platforms, so they can be stored
+pub(crate) struct StableHasher(u64);
+
+impl Default for StableHasher {
+    fn default() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for StableHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    fn write_u16(&mut self, i: u16) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_u64(&mut self, i: u64) {
+        self.write(&i.to_le_bytes());
+    }
+}
+
+/// Interns chunk data, so identical chunks can share the same allocation. This is useful when
+/// loading worlds with large uniform areas, like the sky or oceans
+#[derive(Default)]
+pub struct ChunkCache(HashSet<Arc<ChunkData>>);
+
+impl ChunkCache {
+    /// Get the shared copy of the chunk data, adding it to the cache if no identical chunk has been
+    /// interned yet
+    pub fn intern(&mut self, data: ChunkData) -> Arc<ChunkData> {
+        if let Some(shared) = self.0.get(&data) {
+            return shared.clone();
+        }
+        let shared = Arc::new(data);
+        self.0.insert(shared.clone());
+        shared
+    }
+
+    /// The number of distinct chunks in the cache
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Check if the cache is empty
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Remove all chunks from the cache
+    pub fn clear(&mut self) {
+        self.0.clear();
+    }
+}
+
+/// The bit that marks a material as the start of a run in [Encoding::Materials]
+const MATERIAL_RUN: u8 = 0x80;
 
 impl From<RawChunk> for ChunkData {
     fn from(value: RawChunk) -> Self {
@@ -108,70 +538,597 @@ impl From<RawChunk> for ChunkData {
 
 impl From<&RawChunk> for ChunkData {
     fn from(value: &RawChunk) -> Self {
-        let mut buf = SmallVec::new();
-        let mut last = Voxel::AIR;
-        let mut count = 0u16;
-        for (k, v) in value.0.iter().enumerate() {
-            if k != 0 && last == v.raw() {
-                count += 1;
-                continue;
-            }
-            if count > 1 {
-                buf.push(last.raw());
-                buf.push(count);
-            };
-            buf.push(v.raw());
-            count = 1;
-            last = *v;
-        }
-        if count > 1 {
-            buf.push(last.raw());
-            buf.push(count);
+        let mut encoder = Encoder::default();
+        for v in value.0.iter() {
+            encoder.push(*v, 1);
+        }
+        encoder.finish()
+    }
+}
+
+/// Encodes the voxels on the fly, without first collecting them into a [RawChunk]. The iterator
+/// must yield exactly [CHUNK_VOXELS] voxels, in the same order as they are stored in a [RawChunk]
+impl FromIterator<Voxel> for ChunkData {
+    fn from_iter<T: IntoIterator<Item = Voxel>>(iter: T) -> Self {
+        let mut encoder = Encoder::default();
+        let mut n = 0;
+        for v in iter {
+            encoder.push(v, 1);
+            n += 1;
+        }
+        assert_eq!(
+            CHUNK_VOXELS, n,
+            "Chunks must have exactly CHUNK_VOXELS voxels"
+        );
+        encoder.finish()
+    }
+}
+
+/// Run Length Encodes voxels. Every sequence of voxels has exactly one encoding: a single voxel
+/// is stored as is, and a run of at least 2 voxels is stored as the voxel twice followed by the
+/// length of the run. If every voxel has the same value and only storing the materials is
+/// smaller, the chunk is stored as [Encoding::Materials] instead
+struct Encoder {
+    buf: SmallVec<[u16; INLINE_VOXELS]>,
+    last: u16,
+    count: u16,
+    value: Option<u16>,
+    uniform: bool,
+}
+
+impl Default for Encoder {
+    fn default() -> Self {
+        Self {
+            buf: SmallVec::new(),
+            last: 0,
+            count: 0,
+            value: None,
+            uniform: true,
+        }
+    }
+}
+
+impl Encoder {
+    fn push(&mut self, voxel: Voxel, n: u16) {
+        if n == 0 {
+            return;
+        }
+        match self.value {
+            Some(value) => self.uniform &= value == voxel.value(),
+            None => self.value = Some(voxel.value()),
+        }
+        if self.count > 0 && self.last == voxel.raw() {
+            self.count += n;
+            return;
+        }
+        self.flush();
+        self.last = voxel.raw();
+        self.count = n;
+    }
+
+    fn flush(&mut self) {
+        match self.count {
+            0 => {}
+            1 => self.buf.push(self.last),
+            n => self.buf.extend_from_slice(&[self.last, self.last, n]),
+        }
+        self.count = 0;
+    }
+
+    fn finish(mut self) -> ChunkData {
+        self.flush();
+        let voxels = ChunkData(Encoding::Voxels(self.buf));
+        let Some(value) = self.value.filter(|_| self.uniform) else {
+            return voxels;
         };
 
-        Self(buf)
+        let mut materials = SmallVec::new();
+        for (voxel, mut n) in voxels.encoded_runs() {
+            while n > 0 {
+                let len = n.min(u8::MAX as u16);
+                if len == 1 {
+                    materials.push(voxel.material());
+                } else {
+                    materials.extend_from_slice(&[voxel.material() | MATERIAL_RUN, len as u8]);
+                }
+                n -= len;
+            }
+        }
+
+        let materials = ChunkData(Encoding::Materials { value, materials });
+        if materials.n_bytes() < voxels.n_bytes() {
+            materials
+        } else {
+            voxels
+        }
     }
 }
 
 impl ChunkData {
-    /// The number of bytes the chunk takes up. Since every value is a u16, this is the length * 2
+    /// Create chunk data from an already Run Length Encoded buffer, in the same format
+    /// [ChunkData::from] produces for chunks that aren't stored as materials. The buffer is checked
+    /// with [ChunkData::validate] first, so loading it can't result in a chunk of the wrong size
+    pub fn from_rle(buf: impl IntoIterator<Item = u16>) -> Result<Self, ChunkDataError> {
+        let data = Self(Encoding::Voxels(buf.into_iter().collect()));
+        data.validate()?;
+        Ok(data)
+    }
+
+    /// Create chunk data from an already Run Length Encoded buffer without validating it, see
+    /// [ChunkData::from_rle]
+    ///
+    /// # Safety
+    ///
+    /// The buffer must pass [ChunkData::validate]. Other code relies on chunks holding exactly
+    /// [CHUNK_VOXELS] voxels, and may index out of bounds or produce corrupt meshes otherwise
+    pub unsafe fn from_rle_unchecked(buf: impl IntoIterator<Item = u16>) -> Self {
+        Self(Encoding::Voxels(buf.into_iter().collect()))
+    }
+
+    /// Check that the encoded data describes a valid chunk, meaning it holds exactly
+    /// [CHUNK_VOXELS] voxels and doesn't contain empty runs
+    pub fn validate(&self) -> Result<(), ChunkDataError> {
+        let mut voxels = 0;
+        for (run, (_, n)) in self.encoded_runs().enumerate() {
+            if n == 0 {
+                return Err(ChunkDataError::EmptyRun { run });
+            }
+            voxels += n as usize;
+        }
+        if voxels != CHUNK_VOXELS {
+            return Err(ChunkDataError::WrongLength { voxels });
+        }
+        Ok(())
+    }
+
+    /// The number of bytes the chunk takes up. Voxels are stored as u16, while chunks that only
+    /// store materials use a u8 per material plus the shared u16 value
     pub fn n_bytes(&self) -> usize {
-        self.0.len() * 2
+        match &self.0 {
+            Encoding::Voxels(buf) => buf.len() * 2,
+            Encoding::Materials { materials, .. } => materials.len() + 2,
+        }
+    }
+
+    /// The size of the expanded chunk divided by the size of the encoded chunk
+    pub fn compression_ratio(&self) -> f32 {
+        (CHUNK_VOXELS * 2) as f32 / self.n_bytes() as f32
+    }
+
+    /// Get statistics about the encoded chunk, without expanding it
+    pub fn stats(&self) -> ChunkStats {
+        ChunkStats {
+            runs: self.encoded_runs().count(),
+            distinct_materials: self.material_mask().count_ones() as u8,
+            bytes: self.n_bytes(),
+        }
+    }
+
+    /// Estimate the number of triangles the mesh of the chunk will have, without meshing or
+    /// expanding it. Every change between solid and empty voxels along the runs is counted as a
+    /// surface crossing that results in a quad. This is far from exact, but it grows with the
+    /// complexity of the surface, which is enough for LOD and render budget decisions
+    pub fn estimated_triangles(&self) -> usize {
+        let mut solid = self.encoded_runs().map(|(voxel, _)| voxel.is_solid());
+        let Some(mut last) = solid.next() else {
+            return 0;
+        };
+        let mut crossings = 0;
+        for solid in solid {
+            crossings += (solid != last) as usize;
+            last = solid;
+        }
+        crossings * 2
+    }
+
+    /// Get a bitset with bit `m` set for every material `m` in the chunk, without expanding it
+    pub fn material_mask(&self) -> u64 {
+        self.encoded_runs()
+            .fold(0, |mask, (voxel, _)| mask | (1 << voxel.material()))
+    }
+
+    /// Get the material with the most voxels in the chunk, counting air as material 0. The counts
+    /// are taken from the runs, without expanding the chunk. Ties go to the lowest material
+    pub fn dominant_material(&self) -> u8 {
+        let mut counts = [0usize; Voxel::MATERIALS as usize];
+        for (voxel, n) in self.encoded_runs() {
+            counts[voxel.material() as usize] += n as usize;
+        }
+        let mut dominant = 0;
+        for (material, &count) in counts.iter().enumerate() {
+            if count > counts[dominant] {
+                dominant = material;
+            }
+        }
+        dominant as u8
+    }
+
+    /// Get a readable summary of the encoded runs, as `material:value ×count` entries followed by
+    /// the number of bytes the chunk takes up. This is mostly useful for debugging compression
+    pub fn summary(&self) -> String {
+        let runs: Vec<_> = self
+            .encoded_runs()
+            .map(|(voxel, n)| format!("{}:{} ×{}", voxel.material(), voxel.value(), n))
+            .collect();
+        format!("[{}] {} bytes", runs.join(", "), self.n_bytes())
     }
 
     /// Create chunk data for a chunk that only has empty air voxels
     pub fn air() -> Self {
-        Self(SmallVec::from_slice(&[
+        Self(Encoding::Voxels(SmallVec::from_slice(&[
             Voxel::AIR.raw(),
             Voxel::AIR.raw(),
             CHUNK_VOXELS as u16,
-        ]))
+        ])))
+    }
+
+    /// Get a bitmask with a bit set for every solid voxel, in the same order as the voxels are
+    /// stored in a [RawChunk]. Voxel `i` is stored in bit `i % 64` of word `i / 64`
+    pub fn occupancy_mask(&self) -> OccupancyMask {
+        let mut mask = [0; CHUNK_VOXELS.div_ceil(64)];
+        let mut i = 0;
+        for (voxel, n) in self.encoded_runs() {
+            let end = i + n as usize;
+            if voxel.is_solid() {
+                for bit in i..end {
+                    mask[bit / 64] |= 1 << (bit % 64);
+                }
+            }
+            i = end;
+        }
+        mask
+    }
+
+    /// Check if any voxel in the chunk has the given material, without expanding the chunk
+    pub fn contains_material(&self, material: u8) -> bool {
+        self.encoded_runs()
+            .any(|(voxel, _)| voxel.material() == material)
+    }
+
+    /// Check if every voxel in the chunk is an empty air voxel, like in [ChunkData::air]
+    pub fn is_air(&self) -> bool {
+        self.is_uniform() == Some(Voxel::AIR)
+    }
+
+    /// Check if every voxel in the chunk has a value of 0, regardless of its material. Removing
+    /// terrain keeps the material of the voxels, so a dug out chunk is empty without being
+    /// [ChunkData::is_air]
+    pub fn is_empty(&self) -> bool {
+        self.encoded_runs().all(|(voxel, _)| voxel.value() == 0)
+    }
+
+    /// Get the voxel every voxel in the chunk is equal to, if the chunk only holds a single voxel
+    pub fn is_uniform(&self) -> Option<Voxel> {
+        let mut runs = self.encoded_runs().map(|(voxel, _)| voxel);
+        let first = runs.next()?;
+        runs.all(|voxel| voxel == first).then_some(first)
+    }
+
+    /// Check if two chunks hold the same voxels. Unlike comparing the encoded data, this also
+    /// considers chunks equal when their runs are split or stored differently
+    pub fn content_eq(&self, other: &ChunkData) -> bool {
+        let (mut a, mut b) = (self.encoded_runs(), other.encoded_runs());
+        let (mut run_a, mut run_b) = (a.next(), b.next());
+        loop {
+            match (run_a, run_b) {
+                (None, None) => return true,
+                (Some((voxel_a, n_a)), Some((voxel_b, n_b))) => {
+                    if voxel_a != voxel_b {
+                        return false;
+                    }
+                    let n = n_a.min(n_b);
+                    run_a = if n_a > n {
+                        Some((voxel_a, n_a - n))
+                    } else {
+                        a.next()
+                    };
+                    run_b = if n_b > n {
+                        Some((voxel_b, n_b - n))
+                    } else {
+                        b.next()
+                    };
+                }
+                _ => return false,
+            }
+        }
+    }
+
+    /// Re-encode the chunk so it has the same encoding [ChunkData::from] would produce for its
+    /// voxels. This merges split runs and stores runs of a single voxel inline, making chunks with
+    /// equal voxels byte-identical
+    pub fn canonicalize(&mut self) {
+        let mut encoder = Encoder::default();
+        for (voxel, n) in self.encoded_runs() {
+            encoder.push(voxel, n);
+        }
+        *self = encoder.finish();
+    }
+
+    /// Replace the material of every voxel with the `from` material by the `to` material, like
+    /// [RawChunk::replace_material]. The chunk is re-encoded run by run, without expanding it
+    pub fn replace_material(&mut self, from: u8, to: u8) {
+        let mut encoder = Encoder::default();
+        for (voxel, n) in self.encoded_runs() {
+            if voxel.material() == from {
+                encoder.push(Voxel::new(to, voxel.value()), n);
+            } else {
+                encoder.push(voxel, n);
+            }
+        }
+        *self = encoder.finish();
+    }
+
+    /// Set a few voxels without expanding the chunk to a [RawChunk]. The runs are split around the
+    /// changed voxels and re-encoded in a single pass. When a voxel is changed more than once the
+    /// last change wins, changes outside of the chunk are ignored
+    pub fn update_voxels(&mut self, changes: &[([u32; 3], Voxel)]) {
+        let mut changes: Vec<_> = changes
+            .iter()
+            .rev()
+            .filter(|([x, y, z], _)| in_bounds(*x, *y, *z))
+            .map(|(pos, voxel)| (CHUNK_SHAPE.linearize(*pos) as usize, *voxel))
+            .collect();
+        // The sort is stable, so the last change to each voxel comes first and survives the dedup
+        changes.sort_by_key(|(idx, _)| *idx);
+        changes.dedup_by_key(|(idx, _)| *idx);
+
+        let mut changes = changes.into_iter().peekable();
+        let mut encoder = Encoder::default();
+        let mut i = 0;
+        for (voxel, n) in self.encoded_runs() {
+            let end = i + n as usize;
+            while let Some((idx, new)) = changes.next_if(|(idx, _)| *idx < end) {
+                encoder.push(voxel, (idx - i) as u16);
+                encoder.push(new, 1);
+                i = idx + 1;
+            }
+            encoder.push(voxel, (end - i) as u16);
+            i = end;
+        }
+        *self = encoder.finish();
+    }
+
+    /// Get a hash of the voxels in the chunk, which is the same for equal chunks regardless of how
+    /// they are encoded. The hash is stable across runs, so it can be persisted
+    pub fn content_hash(&self) -> u64 {
+        let mut hasher = StableHasher::default();
+        self.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// Iterate over the encoded runs of the chunk without expanding it, as a voxel and the number
+    /// of times it repeats. A single voxel is a run of 1. Runs are returned as they are stored, so
+    /// chunks that aren't canonical can have consecutive runs of the same voxel, see
+    /// [ChunkData::canonicalize]
+    pub fn runs(&self) -> impl Iterator<Item = (Voxel, u32)> + '_ {
+        self.encoded_runs().map(|(voxel, n)| (voxel, n as u32))
+    }
+
+    fn encoded_runs(&self) -> Runs<'_> {
+        Runs {
+            data: &self.0,
+            k: 0,
+        }
+    }
+
+    /// Convert the chunk to a 3D R16 texture with [CHUNK_SIDES](crate::CHUNK_SIDES) texels per
+    /// side, for renderers that sample the signed distance values on the GPU. Texels are stored in
+    /// the same order as the voxels of a [RawChunk], so x maps to the width, y to the height and z
+    /// to the depth of the texture. The signed distance is mapped from `-1..=1` to `0..=1`, so air
+    /// has the max value and fully solid voxels are 0
+    #[cfg(feature = "image")]
+    pub fn to_image(&self) -> Image {
+        use bevy::render::render_resource::{Extent3d, TextureDimension, TextureFormat};
+
+        let mut data = Vec::with_capacity(CHUNK_VOXELS * 2);
+        for (voxel, n) in self.encoded_runs() {
+            let texel = (Voxel::MAX_VALUE - voxel.value()) as u32 * u16::MAX as u32
+                / Voxel::MAX_VALUE as u32;
+            for _ in 0..n {
+                data.extend_from_slice(&(texel as u16).to_le_bytes());
+            }
+        }
+
+        let side = crate::CHUNK_SIDES as u32;
+        Image::new(
+            Extent3d {
+                width: side,
+                height: side,
+                depth_or_array_layers: side,
+            },
+            TextureDimension::D3,
+            data,
+            TextureFormat::R16Unorm,
+        )
+    }
+
+    /// Convert the chunk to a human readable JSON array of runs, in the form
+    /// `[{"m":material,"v":value,"n":count}, ...]`. Consecutive runs of the same voxel are merged,
+    /// so the same chunk always results in the same JSON. This is meant for external tools, the
+    /// compact binary form is much smaller. See [ChunkData::from_json_runs]
+    #[cfg(feature = "json")]
+    pub fn to_json_runs(&self) -> String {
+        let mut runs: Vec<JsonRun> = Vec::new();
+        for (voxel, n) in self.encoded_runs() {
+            match runs.last_mut() {
+                Some(last) if last.m == voxel.material() && last.v == voxel.value() => {
+                    last.n += n as u32;
+                }
+                _ => runs.push(JsonRun {
+                    m: voxel.material(),
+                    v: voxel.value(),
+                    n: n as u32,
+                }),
+            }
+        }
+        serde_json::to_string(&runs).expect("serializing runs to JSON can't fail")
+    }
+
+    /// Parse a chunk from the JSON form created by [ChunkData::to_json_runs]. Returns None if the
+    /// input isn't valid JSON in that form, a run holds an invalid material or value, or the runs
+    /// don't add up to exactly [CHUNK_VOXELS] voxels
+    #[cfg(feature = "json")]
+    pub fn from_json_runs(json: &str) -> Option<ChunkData> {
+        let runs: Vec<JsonRun> = serde_json::from_str(json).ok()?;
+        let mut voxels = 0;
+        for run in &runs {
+            if run.n == 0 || run.m > Voxel::MAX_MATERIAL || run.v > Voxel::MAX_VALUE {
+                return None;
+            }
+            voxels += run.n as usize;
+            if voxels > CHUNK_VOXELS {
+                return None;
+            }
+        }
+        if voxels != CHUNK_VOXELS {
+            return None;
+        }
+
+        let mut encoder = Encoder::default();
+        for run in runs {
+            encoder.push(Voxel::new(run.m, run.v), run.n as u16);
+        }
+        Some(encoder.finish())
+    }
+
+    /// Compress the chunk further with zstd, for chunks that are archived and not accessed often.
+    /// The runs are stored as pairs of little endian u16s, the voxel followed by the length of the
+    /// run, which zstd then compresses. See [ChunkData::decompress_cold]
+    #[cfg(feature = "zstd")]
+    pub fn compress_cold(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.n_bytes() * 2);
+        for (voxel, n) in self.encoded_runs() {
+            bytes.extend_from_slice(&voxel.raw().to_le_bytes());
+            bytes.extend_from_slice(&n.to_le_bytes());
+        }
+        zstd::encode_all(bytes.as_slice(), 0).expect("compressing into memory can't fail")
+    }
+
+    /// Decompress a chunk compressed with [ChunkData::compress_cold]. Returns None if the data
+    /// isn't valid zstd or doesn't describe a valid chunk
+    #[cfg(feature = "zstd")]
+    pub fn decompress_cold(bytes: &[u8]) -> Option<ChunkData> {
+        let bytes = zstd::decode_all(bytes).ok()?;
+        if bytes.len() % 4 != 0 {
+            return None;
+        }
+        let mut encoder = Encoder::default();
+        let mut voxels = 0;
+        for run in bytes.chunks_exact(4) {
+            let voxel = Voxel::from_raw(u16::from_le_bytes([run[0], run[1]]));
+            let n = u16::from_le_bytes([run[2], run[3]]);
+            voxels += n as usize;
+            if voxels > CHUNK_VOXELS {
+                return None;
+            }
+            encoder.push(voxel, n);
+        }
+        (voxels == CHUNK_VOXELS).then(|| encoder.finish())
     }
 
     /// Expand the ChunkData to a RawChunk, which can then be used to create a chunk mesh or
     /// modify the chunk
     pub fn expand(&self) -> RawChunk {
         let mut buf = RawChunk::empty();
+        for (voxel, n) in self.encoded_runs() {
+            buf.0.resize(buf.0.len() + n as usize, voxel);
+        }
+        debug_assert_eq!(
+            CHUNK_VOXELS,
+            buf.0.len(),
+            "Chunk data must expand to exactly CHUNK_VOXELS voxels, see ChunkData::validate"
+        );
+        buf
+    }
+}
+
+/// Statistics about an encoded chunk, see [ChunkData::stats]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ChunkStats {
+    /// The number of runs the chunk is stored as, a single voxel also counts as a run
+    pub runs: usize,
+    /// The number of different materials in the chunk
+    pub distinct_materials: u8,
+    /// The number of bytes the chunk takes up, see [ChunkData::n_bytes]
+    pub bytes: usize,
+}
+
+/// The error returned when encoded chunk data doesn't describe a valid chunk, see
+/// [ChunkData::validate]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChunkDataError {
+    /// The runs don't add up to exactly [CHUNK_VOXELS] voxels
+    WrongLength {
+        /// The number of voxels the runs add up to
+        voxels: usize,
+    },
+    /// A run has a length of 0
+    EmptyRun {
+        /// The index of the run, counting single voxels as runs
+        run: usize,
+    },
+}
+
+impl std::fmt::Display for ChunkDataError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::WrongLength { voxels } => write!(
+                f,
+                "chunk data holds {} voxels, but chunks have {} voxels",
+                voxels, CHUNK_VOXELS
+            ),
+            Self::EmptyRun { run } => write!(f, "run {} of the chunk data is empty", run),
+        }
+    }
+}
+
+impl std::error::Error for ChunkDataError {}
+
+/// A run of voxels in the JSON form of a chunk, see [ChunkData::to_json_runs]
+#[cfg(feature = "json")]
+#[derive(Serialize, Deserialize)]
+struct JsonRun {
+    m: u8,
+    v: u16,
+    n: u32,
+}
+
+/// An iterator over the runs of a [ChunkData], as a voxel and the number of times it repeats
+struct Runs<'a> {
+    data: &'a Encoding,
+    k: usize,
+}
+
+impl Iterator for Runs<'_> {
+    type Item = (Voxel, u16);
 
-        let len = self.0.len();
-        let mut k = 0;
-        while k < len {
-            let v = self.0[k];
-            if k + 2 < len {
-                let peek = self.0[k + 1];
-                if peek == v {
-                    let n = self.0[k + 2] as usize;
-                    buf.0.resize(buf.0.len() + n, Voxel::from_raw(v));
-                    k += 3;
-                    continue;
+    fn next(&mut self) -> Option<Self::Item> {
+        let k = self.k;
+        match self.data {
+            Encoding::Voxels(buf) => {
+                let v = *buf.get(k)?;
+                if k + 2 < buf.len() && buf[k + 1] == v {
+                    self.k += 3;
+                    return Some((Voxel::from_raw(v), buf[k + 2]));
                 }
+
+                self.k += 1;
+                Some((Voxel::from_raw(v), 1))
             }
+            Encoding::Materials { value, materials } => {
+                let m = *materials.get(k)?;
+                if m & MATERIAL_RUN != 0 && k + 1 < materials.len() {
+                    self.k += 2;
+                    return Some((
+                        Voxel::new(m & !MATERIAL_RUN, *value),
+                        materials[k + 1] as u16,
+                    ));
+                }
 
-            buf.0.push(Voxel::from_raw(v));
-            k += 1;
+                self.k += 1;
+                Some((Voxel::new(m & !MATERIAL_RUN, *value), 1))
+            }
         }
-
-        buf
     }
 }
 
@@ -184,7 +1141,44 @@ fn test_rle() {
     input.push(Voxel::new(0, 1));
 
     let output = ChunkData::from(RawChunk(input));
-    assert_eq!(&output.0.as_slice(), &[12, 12, 10, 0, 29, 29, 8, 1]);
+    assert_eq!(
+        Encoding::Voxels(SmallVec::from_slice(&[12, 12, 10, 0, 29, 29, 8, 1])),
+        output.0
+    );
+}
+
+#[test]
+fn test_rle_roundtrip_random() {
+    // A small xorshift generator, so the test is reproducible without extra dependencies
+    let mut state = 0x2545_f491_u32;
+    let mut next = move || {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        state
+    };
+
+    for _ in 0..200 {
+        let mut voxels = Vec::with_capacity(CHUNK_VOXELS);
+        while voxels.len() < CHUNK_VOXELS {
+            let voxel = Voxel::new((next() % 3) as u8, (next() % 3) as u16);
+            let n = ((1 + next() % 4) as usize).min(CHUNK_VOXELS - voxels.len());
+            voxels.extend(std::iter::repeat_n(voxel, n));
+        }
+
+        let data = ChunkData::from(RawChunk(voxels.clone()));
+        assert_eq!(voxels, data.expand().0);
+    }
+}
+
+#[test]
+fn test_rle_roundtrip_trailing_single() {
+    let mut voxels = vec![Voxel::new(1, 10); CHUNK_VOXELS];
+    voxels[CHUNK_VOXELS - 2] = Voxel::new(2, 10);
+    voxels[CHUNK_VOXELS - 1] = Voxel::new(1, 10);
+
+    let data = ChunkData::from(RawChunk(voxels.clone()));
+    assert_eq!(voxels, data.expand().0);
 }
 
 #[test]
@@ -193,16 +1187,863 @@ fn test_rle_all_air_fits_in_smallvec() {
     input.extend_from_slice(&[Voxel::AIR; 1024]);
 
     let output = ChunkData::from(RawChunk(input));
-    assert_eq!(output.0.len(), 3);
-    assert_eq!(output.0.as_slice(), &[Voxel::AIR.raw(), Voxel::AIR.raw(), 1024]);
+    let Encoding::Voxels(buf) = output.0 else {
+        panic!("Expected voxel encoding");
+    };
+    assert_eq!(buf.len(), 3);
+    assert_eq!(buf.as_slice(), &[Voxel::AIR.raw(), Voxel::AIR.raw(), 1024]);
 }
 
 #[test]
 fn test_rle_expand() {
-    let mut rle = ChunkData(SmallVec::new());
-    rle.0.extend_from_slice(&[1, 1, 2, 3, 3, 4, 5]);
+    let rle = ChunkData(Encoding::Voxels(SmallVec::from_slice(&[
+        1,
+        1,
+        2,
+        3,
+        3,
+        CHUNK_VOXELS as u16 - 3,
+        5,
+    ])));
 
     let output = rle.expand();
-    assert_eq!(output.0.as_slice(), &[1, 1, 3, 3, 3, 3, 5]);
-    assert_eq!(output.0.capacity(), 8000);
+    assert_eq!(output.0.len(), CHUNK_VOXELS);
+    assert_eq!(output.0[..5], [1, 1, 3, 3, 3]);
+    assert_eq!(output.0[CHUNK_VOXELS - 2..], [3, 5]);
+    assert_eq!(output.0.capacity(), CHUNK_VOXELS);
+    assert_eq!(ChunkData::air().expand().0.len(), CHUNK_VOXELS);
+}
+
+#[test]
+#[cfg(debug_assertions)]
+#[should_panic]
+fn test_rle_expand_wrong_length() {
+    let _ = ChunkData(Encoding::Voxels(SmallVec::from_slice(&[1, 1, 2, 3]))).expand();
+}
+
+#[test]
+fn test_from_transform() {
+    let center = ChunkPosition::new(1, -2, 0);
+    let transform = Transform::from_translation(center.get_translation());
+    assert_eq!(
+        Some((center, Vec3::splat(CHUNK_SIZE / 2.))),
+        ChunkPosition::from_transform(&transform)
+    );
+
+    // The mesh vertex of voxel (10, 11, 5) lies at (0.75, 1.5, -3), so an edit centered on the
+    // object is centered on that voxel
+    use crate::{
+        edit::{ChunkModifier, Mode, SphereSdf},
+        VoxelSize,
+    };
+    let transform = Transform::from_xyz(0.75, 1.5, -3.);
+    let (chunk, relative) = ChunkPosition::from_transform(&transform).unwrap();
+    assert_eq!(ChunkPosition::new(0, 0, 0), chunk);
+    let mut modifier = ChunkModifier::default();
+    modifier.apply_sdf(
+        chunk,
+        &mut ChunkMap::default(),
+        |_| unreachable!(),
+        SphereSdf(1.),
+        Mode::Add,
+        1,
+        0.,
+        relative,
+    );
+    let edited = modifier.modified_chunk(chunk).unwrap();
+    assert_eq!(Voxel::MAX_VALUE, edited.get_voxel(10, 11, 5).value());
+    assert_eq!(
+        Some((chunk, relative)),
+        VoxelSize::default().relative_position(transform.translation)
+    );
+
+    // With other voxel sizes the same voxel lies elsewhere
+    let voxel_size = VoxelSize(Vec3::new(1., 1.5, 0.5));
+    let (chunk, relative) = voxel_size
+        .relative_position(Vec3::new(1., 3., -2.))
+        .unwrap();
+    assert_eq!(ChunkPosition::new(0, 0, 0), chunk);
+    let mut modifier = ChunkModifier::default();
+    modifier.set_voxel_size(voxel_size);
+    modifier.apply_sdf(
+        chunk,
+        &mut ChunkMap::default(),
+        |_| unreachable!(),
+        SphereSdf(1.),
+        Mode::Add,
+        1,
+        0.,
+        relative,
+    );
+    let edited = modifier.modified_chunk(chunk).unwrap();
+    assert_eq!(Voxel::MAX_VALUE, edited.get_voxel(10, 11, 5).value());
+
+    assert_eq!(
+        None,
+        ChunkPosition::from_transform(&Transform::from_xyz(0., 1e6, 0.))
+    );
+    assert_eq!(None, voxel_size.relative_position(Vec3::new(0., 1e6, 0.)));
+}
+
+#[test]
+fn test_chunk_aabb() {
+    let (min, max) = ChunkPosition::new(0, 0, 0).aabb();
+    assert_eq!(Vec3::splat(-CHUNK_SIZE / 2.), min);
+    assert_eq!(Vec3::splat(CHUNK_SIZE / 2.), max);
+
+    let (min, max) = ChunkPosition::new(1, 0, -1).aabb();
+    assert_eq!(
+        Vec3::new(CHUNK_SIZE / 2., -CHUNK_SIZE / 2., -CHUNK_SIZE * 1.5),
+        min
+    );
+    assert_eq!(
+        Vec3::new(CHUNK_SIZE * 1.5, CHUNK_SIZE / 2., -CHUNK_SIZE / 2.),
+        max
+    );
+}
+
+#[test]
+fn test_get_or_spawn() {
+    use bevy::ecs::system::CommandQueue;
+
+    let mut world = World::default();
+    let mut queue = CommandQueue::default();
+    let mut chunk_map = ChunkMap::default();
+
+    let mut commands = Commands::new(&mut queue, &world);
+    let first = chunk_map.get_or_spawn(ChunkPosition::new(1, 2, 3), &mut commands);
+    let second = chunk_map.get_or_spawn(ChunkPosition::new(1, 2, 3), &mut commands);
+    queue.apply(&mut world);
+
+    assert_eq!(first, second);
+    assert_eq!(1, chunk_map.len());
+    assert_eq!(1, world.query::<&ChunkPosition>().iter(&world).count());
+}
+
+#[test]
+fn test_content_eq() {
+    let a = ChunkData(Encoding::Voxels(SmallVec::from_slice(&[
+        1, 1, 2, 3, 3, 4, 5,
+    ])));
+    let b = ChunkData(Encoding::Voxels(SmallVec::from_slice(&[
+        1, 1, 1, 1, 3, 3, 2, 3, 3, 2, 5,
+    ])));
+    assert!(a.content_eq(&b));
+    assert!(b.content_eq(&a));
+
+    let c = ChunkData(Encoding::Voxels(SmallVec::from_slice(&[
+        1, 1, 2, 3, 3, 3, 5,
+    ])));
+    assert!(!a.content_eq(&c));
+    let d = ChunkData(Encoding::Voxels(SmallVec::from_slice(&[1, 1, 2, 3, 3, 4])));
+    assert!(!a.content_eq(&d));
+}
+
+#[test]
+fn test_rle_canonical() {
+    let mut voxels = vec![Voxel::AIR; CHUNK_VOXELS];
+    voxels[0] = Voxel::new(1, 50);
+    voxels[1] = Voxel::new(1, 50);
+    voxels[2] = Voxel::new(2, 50);
+    voxels[CHUNK_VOXELS - 1] = Voxel::new(3, 7);
+
+    let a = ChunkData::from(RawChunk(voxels.clone()));
+    let b = ChunkData::from(RawChunk(voxels));
+    assert_eq!(a.0, b.0);
+
+    let air = Voxel::AIR.raw();
+    let (v1, v2, v3) = (
+        Voxel::new(1, 50).raw(),
+        Voxel::new(2, 50).raw(),
+        Voxel::new(3, 7).raw(),
+    );
+    let mut split = ChunkData(Encoding::Voxels(SmallVec::from_slice(&[
+        v1,
+        v1,
+        1,
+        v1,
+        v2,
+        v2,
+        1,
+        air,
+        air,
+        3000,
+        air,
+        air,
+        CHUNK_VOXELS as u16 - 3004,
+        v3,
+    ])));
+    assert!(split.content_eq(&a));
+    split.canonicalize();
+    assert_eq!(a.0, split.0);
+}
+
+#[test]
+fn test_from_rle() {
+    let valid = [1, 1, CHUNK_VOXELS as u16 - 2, 3, 4];
+    let data = ChunkData::from_rle(valid).unwrap();
+    assert_eq!(data.expand().0.len(), CHUNK_VOXELS);
+    assert_eq!(data.expand().0[CHUNK_VOXELS - 1], Voxel::from_raw(4));
+
+    let short = vec![1, 1, 100];
+    assert_eq!(
+        ChunkData::from_rle(short).unwrap_err(),
+        ChunkDataError::WrongLength { voxels: 100 }
+    );
+
+    let empty = [2, 1, 1, 0, 3, 3, CHUNK_VOXELS as u16 - 1];
+    assert_eq!(
+        ChunkData::from_rle(empty).unwrap_err(),
+        ChunkDataError::EmptyRun { run: 1 }
+    );
+
+    assert_eq!(ChunkData::air().validate(), Ok(()));
+}
+
+#[test]
+fn test_rle_from_iter() {
+    let voxel = |i: usize| Voxel::new((i / 900) as u8, (i % 7) as u16 / 3);
+
+    let raw = RawChunk((0..CHUNK_VOXELS).map(voxel).collect());
+    let streamed = ChunkData::from_iter((0..CHUNK_VOXELS).map(voxel));
+    assert_eq!(ChunkData::from(raw).0, streamed.0);
+}
+
+#[test]
+#[should_panic]
+fn test_rle_from_iter_wrong_size() {
+    let _ = ChunkData::from_iter([Voxel::AIR; 10]);
+}
+
+#[test]
+fn test_rle_materials_only() {
+    let voxels: Vec<_> = (0..CHUNK_VOXELS)
+        .map(|i| Voxel::new(((i / 3) % 4) as u8, Voxel::MAX_VALUE))
+        .collect();
+
+    let data = ChunkData::from(RawChunk(voxels.clone()));
+    assert!(matches!(data.0, Encoding::Materials { .. }));
+    assert_eq!(voxels, data.expand().0);
+
+    // The same chunk with varying values can't use the material encoding
+    let mut varying = voxels.clone();
+    varying[0] = Voxel::new(0, 0);
+    let varying = ChunkData::from(RawChunk(varying));
+    assert!(matches!(varying.0, Encoding::Voxels(_)));
+    assert!(data.n_bytes() * 2 < varying.n_bytes());
+}
+
+#[test]
+fn test_serde_chunk_data() {
+    // Chunks saved as plain voxel runs, like every chunk was before material-only chunks
+    let air: ChunkData = serde_json::from_str(&format!("[0,0,{CHUNK_VOXELS}]")).unwrap();
+    assert_eq!(ChunkData::air().0, air.0);
+
+    // Chunks stored as materials are saved in that same format, and loaded as materials again
+    let voxels: Vec<_> = (0..CHUNK_VOXELS)
+        .map(|i| Voxel::new(((i / 3) % 4) as u8, Voxel::MAX_VALUE))
+        .collect();
+    let data = ChunkData::from(RawChunk(voxels.clone()));
+    assert!(matches!(data.0, Encoding::Materials { .. }));
+    let runs: Vec<u16> = voxels
+        .chunks(3)
+        .flat_map(|run| [run[0].raw(), run[0].raw(), run.len() as u16])
+        .collect();
+    let json = serde_json::to_string(&data).unwrap();
+    assert_eq!(serde_json::to_string(&runs).unwrap(), json);
+    assert_eq!(data.0, serde_json::from_str::<ChunkData>(&json).unwrap().0);
+
+    assert!(serde_json::from_str::<ChunkData>("[0,0,10]").is_err());
+}
+
+#[test]
+fn test_rle_materials_long_runs() {
+    let mut voxels = vec![Voxel::new(5, Voxel::MAX_VALUE); CHUNK_VOXELS];
+    for v in voxels.iter_mut().skip(3).step_by(700) {
+        *v = Voxel::new(6, Voxel::MAX_VALUE);
+    }
+
+    let data = ChunkData::from(RawChunk(voxels.clone()));
+    assert!(matches!(data.0, Encoding::Materials { .. }));
+    assert_eq!(voxels, data.expand().0);
+}
+
+#[test]
+fn test_is_uniform() {
+    assert_eq!(Some(Voxel::AIR), ChunkData::air().is_uniform());
+
+    let mut chunk = RawChunk::air();
+    chunk.set_voxel(3, 4, 5, Voxel::new(1, 1));
+    assert_eq!(None, ChunkData::from(chunk).is_uniform());
+}
+
+#[test]
+fn test_occupancy_mask() {
+    let mut chunk = RawChunk::air();
+    for x in 0..crate::CHUNK_BOUNDS {
+        chunk.set_voxel(x, 3, 7, Voxel::new(1, Voxel::MAX_VALUE));
+    }
+    chunk.set_voxel(19, 19, 19, Voxel::new(2, Voxel::MAX_VALUE));
+    chunk.set_voxel(5, 5, 5, Voxel::new(2, 10));
+
+    let mask = ChunkData::from(&chunk).occupancy_mask();
+    for (i, voxel) in chunk.0.iter().enumerate() {
+        assert_eq!(voxel.is_solid(), mask[i / 64] & (1 << (i % 64)) != 0);
+    }
+}
+
+#[test]
+fn test_stats() {
+    let air = ChunkData::air();
+    assert_eq!(
+        ChunkStats {
+            runs: 1,
+            distinct_materials: 1,
+            bytes: 6,
+        },
+        air.stats()
+    );
+
+    let mut chunk = RawChunk::air();
+    chunk.set_voxel(1, 2, 3, Voxel::new(4, 100));
+    let chunk = ChunkData::from(chunk);
+    assert_eq!(3, chunk.stats().runs);
+    assert_eq!(2, chunk.stats().distinct_materials);
+
+    // Nothing compresses better than a chunk with a single run
+    assert_eq!((CHUNK_VOXELS * 2) as f32 / 6., air.compression_ratio());
+    assert!(air.compression_ratio() > chunk.compression_ratio());
+}
+
+#[test]
+fn test_dominant_material() {
+    let voxels: Vec<_> = (0..CHUNK_VOXELS)
+        .map(|i| match i % 5 {
+            0 | 2 | 4 => Voxel::new(3, Voxel::MAX_VALUE),
+            _ => Voxel::new(5, Voxel::MAX_VALUE),
+        })
+        .collect();
+    assert_eq!(ChunkData::from(RawChunk(voxels)).dominant_material(), 3);
+    assert_eq!(ChunkData::air().dominant_material(), 0);
+}
+
+#[test]
+fn test_contains_material() {
+    let mut chunk = RawChunk::air();
+    chunk.set_voxel(3, 4, 5, Voxel::new(2, Voxel::MAX_VALUE));
+    let data = ChunkData::from(chunk);
+    assert!(data.contains_material(0));
+    assert!(data.contains_material(2));
+    assert!(!data.contains_material(1));
+}
+
+#[cfg(feature = "image")]
+#[test]
+fn test_to_image() {
+    use crate::CHUNK_SIDES;
+
+    let image = ChunkData::air().to_image();
+    let size = image.texture_descriptor.size;
+    assert_eq!(
+        [CHUNK_SIDES as u32; 3],
+        [size.width, size.height, size.depth_or_array_layers]
+    );
+    assert_eq!(CHUNK_VOXELS * 2, image.data.len());
+    assert!(image.data.iter().all(|b| *b == u8::MAX));
+
+    let mut chunk = RawChunk::air();
+    chunk.set_voxel(1, 2, 3, Voxel::new(1, Voxel::MAX_VALUE));
+    let image = ChunkData::from(chunk).to_image();
+    let texel = CHUNK_SHAPE.linearize([1, 2, 3]) as usize * 2;
+    assert_eq!([0, 0], image.data[texel..texel + 2]);
+}
+
+#[test]
+fn test_runs() {
+    let runs: Vec<_> = ChunkData::air().runs().collect();
+    assert_eq!(vec![(Voxel::AIR, CHUNK_VOXELS as u32)], runs);
+
+    let mut chunk = RawChunk::air();
+    chunk.set_voxel(0, 0, 0, Voxel::new(1, 5));
+    let runs: Vec<_> = ChunkData::from(chunk).runs().collect();
+    assert_eq!(
+        vec![(Voxel::new(1, 5), 1), (Voxel::AIR, CHUNK_VOXELS as u32 - 1)],
+        runs
+    );
+}
+
+#[cfg(feature = "zstd")]
+#[test]
+fn test_compress_cold() {
+    let voxels: Vec<_> = (0..CHUNK_VOXELS)
+        .map(|i| Voxel::new((i % 4) as u8, (i % 3) as u16))
+        .collect();
+    let data = ChunkData::from(RawChunk(voxels.clone()));
+    let cold = data.compress_cold();
+    assert!(cold.len() < data.n_bytes());
+
+    let restored = ChunkData::decompress_cold(&cold).unwrap();
+    assert_eq!(voxels, restored.expand().0);
+    assert_eq!(data.n_bytes(), restored.n_bytes());
+
+    assert_eq!(None, ChunkData::decompress_cold(&[1, 2, 3]));
+    let short = zstd::encode_all(&[0u8, 0, 10, 0][..], 0).unwrap();
+    assert_eq!(None, ChunkData::decompress_cold(&short));
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn test_json_runs() {
+    let voxels: Vec<_> = (0..CHUNK_VOXELS)
+        .map(|i| Voxel::new((i / 1000) as u8, (i / 300 % 5) as u16 * 200))
+        .collect();
+    let data = ChunkData::from(RawChunk(voxels.clone()));
+    let json = data.to_json_runs();
+    assert!(json.starts_with(r#"[{"m":0,"v":0,"n":300},{"m":0,"v":200,"n":300}"#));
+
+    let parsed = ChunkData::from_json_runs(&json).unwrap();
+    assert_eq!(data, parsed);
+    assert_eq!(voxels, parsed.expand().0);
+
+    let air = ChunkData::from_json_runs(r#"[{"m":0,"v":0,"n":8000}]"#).unwrap();
+    assert_eq!(ChunkData::air().expand().0, air.expand().0);
+
+    for invalid in [
+        r#"[{"m":0,"v":0,"n":7999}]"#,
+        r#"[{"m":0,"v":0,"n":8000},{"m":1,"v":0,"n":1}]"#,
+        r#"[{"m":0,"v":0,"n":0},{"m":0,"v":0,"n":8000}]"#,
+        r#"[{"m":64,"v":0,"n":8000}]"#,
+        r#"[{"m":0,"v":1024,"n":8000}]"#,
+        r#"{"m":0,"v":0,"n":8000}"#,
+    ] {
+        assert_eq!(None, ChunkData::from_json_runs(invalid), "{invalid}");
+    }
+}
+
+#[test]
+fn test_estimated_triangles() {
+    assert_eq!(0, ChunkData::air().estimated_triangles());
+    let solid = vec![Voxel::new(1, Voxel::MAX_VALUE); CHUNK_VOXELS];
+    assert_eq!(0, ChunkData::from(RawChunk(solid)).estimated_triangles());
+
+    let floor: Vec<_> = (0..CHUNK_VOXELS)
+        .map(|i| {
+            if i < CHUNK_VOXELS / 2 {
+                Voxel::new(1, Voxel::MAX_VALUE)
+            } else {
+                Voxel::AIR
+            }
+        })
+        .collect();
+    let floor = ChunkData::from(RawChunk(floor)).estimated_triangles();
+
+    let checkerboard: Vec<_> = (0..CHUNK_VOXELS)
+        .map(|i| {
+            if i % 2 == 0 {
+                Voxel::new(1, Voxel::MAX_VALUE)
+            } else {
+                Voxel::AIR
+            }
+        })
+        .collect();
+    let checkerboard = ChunkData::from(RawChunk(checkerboard)).estimated_triangles();
+    assert!(floor > 0);
+    assert!(checkerboard > 1000);
+    assert!(checkerboard > floor);
+}
+
+#[test]
+fn test_is_valid() {
+    assert!(ChunkPosition::is_valid(0, 0, 0));
+    assert!(ChunkPosition::is_valid(i8::MAX as i32, i8::MIN as i32, 5));
+    assert!(!ChunkPosition::is_valid(i8::MAX as i32 + 1, 0, 0));
+    assert!(!ChunkPosition::is_valid(0, i8::MIN as i32 - 1, 0));
+    assert!(!ChunkPosition::is_valid(0, 0, 1000));
+}
+
+#[test]
+fn test_chunk_cache() {
+    use std::collections::hash_map::DefaultHasher;
+
+    let hash = |data: &ChunkData| {
+        let mut hasher = DefaultHasher::new();
+        data.hash(&mut hasher);
+        hasher.finish()
+    };
+
+    let air = ChunkData::air();
+    let split_air = ChunkData(Encoding::Voxels(SmallVec::from_slice(&[
+        0,
+        0,
+        3000,
+        0,
+        0,
+        CHUNK_VOXELS as u16 - 3000,
+    ])));
+    assert_eq!(air, split_air);
+    assert_eq!(hash(&air), hash(&split_air));
+
+    let mut cache = ChunkCache::default();
+    let a = cache.intern(air);
+    let b = cache.intern(split_air);
+    let c = cache.intern(ChunkData::from(RawChunk::air()));
+    assert!(Arc::ptr_eq(&a, &b));
+    assert!(Arc::ptr_eq(&a, &c));
+
+    let mut chunk = RawChunk::air();
+    chunk.set_voxel(0, 0, 0, Voxel::new(1, 1));
+    let d = cache.intern(ChunkData::from(chunk));
+    assert!(!Arc::ptr_eq(&a, &d));
+    assert_eq!(2, cache.len());
+}
+
+#[test]
+fn test_iter_region() {
+    let mut world = World::default();
+    let mut chunk_map = ChunkMap::default();
+    for pos in [
+        ChunkPosition::new(0, 0, 0),
+        ChunkPosition::new(1, 1, 0),
+        ChunkPosition::new(1, 1, 1),
+        ChunkPosition::new(2, 0, 0),
+        ChunkPosition::new(-1, 0, 0),
+    ] {
+        let entity = world.spawn(pos).id();
+        chunk_map.insert(pos, entity);
+    }
+
+    let mut region: Vec<_> = chunk_map
+        .iter_region(ChunkPosition::new(0, 0, 0), ChunkPosition::new(1, 1, 1))
+        .map(|(pos, entity)| {
+            assert_eq!(chunk_map[&pos], entity);
+            pos
+        })
+        .collect();
+    region.sort();
+    assert_eq!(
+        vec![
+            ChunkPosition::new(0, 0, 0),
+            ChunkPosition::new(1, 1, 0),
+            ChunkPosition::new(1, 1, 1),
+        ],
+        region
+    );
+
+    // The edges of the world don't overflow
+    let edge = ChunkPosition::new(i8::MAX, i8::MAX, i8::MAX);
+    assert_eq!(0, chunk_map.iter_region(edge, edge).count());
+}
+
+#[test]
+fn test_materials_in_region() {
+    use bevy::ecs::system::SystemState;
+
+    let mut world = World::default();
+    let mut chunk_map = ChunkMap::default();
+    for (pos, material) in [
+        (ChunkPosition::new(0, 0, 0), 1),
+        (ChunkPosition::new(1, 0, 0), 3),
+        (ChunkPosition::new(1, 1, 1), 7),
+        (ChunkPosition::new(2, 0, 0), 5),
+    ] {
+        let voxels = vec![Voxel::new(material, Voxel::MAX_VALUE); CHUNK_VOXELS];
+        let entity = world.spawn((pos, ChunkData::from(RawChunk(voxels)))).id();
+        chunk_map.insert(pos, entity);
+    }
+
+    let mut state: SystemState<Query<&ChunkData>> = SystemState::new(&mut world);
+    let query = state.get(&world);
+    let mask = materials_in_region(
+        ChunkPosition::new(0, 0, 0),
+        ChunkPosition::new(1, 1, 1),
+        &chunk_map,
+        &query,
+    );
+    assert_eq!((1 << 1) | (1 << 3) | (1 << 7), mask);
+}
+
+#[test]
+fn test_chunk_load_order() {
+    let center = ChunkPosition::new(3, -2, 0);
+    let order = chunk_load_order(center, 2);
+    assert_eq!(125, order.len());
+    assert_eq!(center, order[0]);
+    assert_eq!(ChunkPosition::new(5, 0, 2), order[124]);
+    assert!(order
+        .windows(2)
+        .all(|w| w[0].chebyshev_distance(&center) <= w[1].chebyshev_distance(&center)));
+
+    // Positions outside of the world are skipped
+    let edge = chunk_load_order(ChunkPosition::new(i8::MAX, 0, 0), 1);
+    assert_eq!(18, edge.len());
+}
+
+#[test]
+fn test_replace_material() {
+    let mut chunk = RawChunk::air();
+    chunk.set_voxel(0, 0, 0, Voxel::new(1, 800));
+    for x in 0..10 {
+        chunk.set_voxel(x, 1, 0, Voxel::new(2, 900));
+    }
+    let mut data = ChunkData::from(&chunk);
+    data.replace_material(2, 5);
+    chunk.replace_material(2, 5);
+
+    assert_eq!(ChunkData::from(&chunk), data);
+    let expanded = data.expand();
+    assert_eq!(Voxel::new(1, 800), expanded.get_voxel(0, 0, 0));
+    assert_eq!(Voxel::new(5, 900), expanded.get_voxel(9, 1, 0));
+}
+
+#[test]
+fn test_content_hash() {
+    let mut chunk = RawChunk::air();
+    chunk.set_voxel(3, 4, 5, Voxel::new(2, 700));
+    let data = ChunkData::from(&chunk);
+    assert_eq!(data.content_hash(), ChunkData::from(&chunk).content_hash());
+    assert_ne!(ChunkData::air().content_hash(), data.content_hash());
+
+    // Equal content with split runs still hashes the same
+    let split = ChunkData(Encoding::Voxels(SmallVec::from_slice(&[
+        Voxel::AIR.raw(),
+        Voxel::AIR.raw(),
+        4000,
+        Voxel::AIR.raw(),
+        Voxel::AIR.raw(),
+        4000,
+    ])));
+    assert_eq!(ChunkData::air().content_hash(), split.content_hash());
+}
+
+#[test]
+fn test_chunk_wireframes() {
+    let mut chunk_map = ChunkMap::default();
+    let pos = ChunkPosition::new(0, 0, 0);
+    chunk_map.insert(pos, Entity::PLACEHOLDER);
+
+    let wireframes: Vec<_> = chunk_map.chunk_wireframes().collect();
+    assert_eq!(vec![pos.aabb()], wireframes);
+    assert_eq!(
+        (Vec3::splat(-CHUNK_SIZE / 2.), Vec3::splat(CHUNK_SIZE / 2.)),
+        wireframes[0]
+    );
+}
+
+#[test]
+fn test_update_voxels() {
+    let mut chunk = RawChunk::air();
+    for x in 0..20 {
+        chunk.set_voxel(x, 0, 0, Voxel::new(1, 900));
+    }
+    let mut data = ChunkData::from(&chunk);
+
+    let changes = [
+        ([0, 0, 0], Voxel::new(2, 800)),
+        ([5, 0, 0], Voxel::new(3, 700)),
+        ([19, 19, 19], Voxel::new(1, 600)),
+    ];
+    data.update_voxels(&changes);
+    for ([x, y, z], voxel) in changes {
+        chunk.set_voxel(x, y, z, voxel);
+    }
+
+    assert_eq!(ChunkData::from(&chunk), data);
+    assert_eq!(chunk.0, data.expand().0);
+}
+
+#[test]
+fn test_chunk_map_capacity() {
+    let mut chunk_map = ChunkMap::with_capacity(10_000);
+    assert!(chunk_map.stats().1 >= 10_000);
+    for x in 0..10 {
+        chunk_map.insert(ChunkPosition::new(x, 0, 0), Entity::PLACEHOLDER);
+    }
+    assert_eq!(10, chunk_map.stats().0);
+    assert!(chunk_map.stats().1 >= 10_000);
+    assert_eq!(
+        Some(ChunkSlot::Entity(Entity::PLACEHOLDER)),
+        chunk_map.slot(ChunkPosition::new(3, 0, 0))
+    );
+    assert!(ChunkMap::default().stats().1 >= 500);
+}
+
+#[test]
+fn test_mark_air() {
+    let mut chunk_map = ChunkMap::default();
+    let pos = ChunkPosition::new(1, 2, 3);
+    assert_eq!(None, chunk_map.slot(pos));
+
+    chunk_map.mark_air(pos);
+    assert_eq!(Some(ChunkSlot::Air), chunk_map.slot(pos));
+    assert!(chunk_map.is_empty());
+
+    chunk_map.insert(pos, Entity::PLACEHOLDER);
+    assert_eq!(
+        Some(ChunkSlot::Entity(Entity::PLACEHOLDER)),
+        chunk_map.slot(pos)
+    );
+    assert!(chunk_map.unmark_air(pos));
+    assert!(!chunk_map.unmark_air(pos));
+}
+
+#[test]
+fn test_prune_empty_chunks() {
+    use crate::edit::{BoxSdf, ChunkModifier, Mode};
+
+    let mut world = World::default();
+    let mut chunk_map = ChunkMap::default();
+    let air_pos = ChunkPosition::new(0, 0, 0);
+    let air = world.spawn((air_pos, ChunkData::air())).id();
+    chunk_map.insert(air_pos, air);
+
+    // Fill a chunk with rock and dig it out again, which leaves the rock material behind
+    let dug_pos = ChunkPosition::new(2, 0, 0);
+    let mut modifier = ChunkModifier::default();
+    for mode in [Mode::Add, Mode::Remove] {
+        let center = Vec3::splat(CHUNK_SIZE / 2.);
+        let sdf = BoxSdf(Vec3::splat(CHUNK_SIZE));
+        let getter = |_| unreachable!();
+        modifier.apply_sdf(dug_pos, &mut chunk_map, getter, sdf, mode, 1, 0., center);
+    }
+    let dug_data = ChunkData::from(modifier.modified_chunk(dug_pos).unwrap());
+    assert!(!dug_data.is_air());
+    assert!(dug_data.is_empty());
+    let dug = world.spawn((dug_pos, dug_data)).id();
+    chunk_map.insert(dug_pos, dug);
+
+    let solid_pos = ChunkPosition::new(1, 0, 0);
+    let mut chunk = RawChunk::air();
+    chunk.set_voxel(3, 4, 5, Voxel::new(1, Voxel::MAX_VALUE));
+    let solid = world.spawn((solid_pos, ChunkData::from(chunk))).id();
+    chunk_map.insert(solid_pos, solid);
+    world.insert_resource(chunk_map);
+
+    let mut schedule = Schedule::default();
+    schedule.add_systems(prune_empty_chunks);
+    schedule.run(&mut world);
+
+    assert!(world.get_entity(air).is_none());
+    assert!(world.get_entity(dug).is_none());
+    assert!(world.get_entity(solid).is_some());
+    let chunk_map = world.resource::<ChunkMap>();
+    assert_eq!(Some(ChunkSlot::Air), chunk_map.slot(air_pos));
+    assert_eq!(Some(ChunkSlot::Air), chunk_map.slot(dug_pos));
+    assert_eq!(Some(ChunkSlot::Entity(solid)), chunk_map.slot(solid_pos));
+}
+
+#[test]
+fn test_compress_chunks() {
+    let chunks: Vec<_> = (0..16u32)
+        .map(|i| {
+            let mut chunk = RawChunk::air();
+            for x in 0..i {
+                chunk.set_voxel(x, i, x, Voxel::new(i as u8 % 4, 60 * i as u16));
+            }
+            chunk
+        })
+        .collect();
+
+    let compressed = compress_chunks(&chunks);
+    assert_eq!(chunks.len(), compressed.len());
+    for (chunk, data) in chunks.iter().zip(compressed.iter()) {
+        assert_eq!(ChunkData::from(chunk).0, data.0);
+    }
+}
+
+#[test]
+fn test_summary() {
+    assert_eq!(
+        format!("[0:0 ×{CHUNK_VOXELS}] 6 bytes"),
+        ChunkData::air().summary()
+    );
+
+    let data = ChunkData(Encoding::Voxels(SmallVec::from_slice(&[
+        Voxel::new(1, 600).raw(),
+        Voxel::AIR.raw(),
+        Voxel::AIR.raw(),
+        7999,
+    ])));
+    assert_eq!("[1:600 ×1, 0:0 ×7999] 8 bytes", data.summary());
+}
+
+#[test]
+fn test_encode_positions() {
+    let mut positions = Vec::new();
+    for x in -1..2 {
+        for y in 3..6 {
+            for z in -2..1 {
+                positions.push(ChunkPosition::new(x, y, z));
+            }
+        }
+    }
+    positions.reverse();
+    positions.push(ChunkPosition::new(0, 4, -1));
+
+    let bytes = encode_positions(&positions);
+    assert!(bytes.len() < 3 * positions.len());
+
+    positions.sort();
+    positions.dedup();
+    assert_eq!(Some(positions), decode_positions(&bytes));
+
+    let far = [
+        ChunkPosition::new(-128, -128, -128),
+        ChunkPosition::new(127, 127, 127),
+    ];
+    assert_eq!(
+        Some(far.to_vec()),
+        decode_positions(&encode_positions(&far))
+    );
+    assert_eq!(Some(Vec::new()), decode_positions(&[]));
+
+    // A varint that is cut off, and one past the last possible position
+    assert_eq!(None, decode_positions(&[0x80]));
+    assert_eq!(None, decode_positions(&[0x80, 0x80, 0x80, 0x08]));
+}
+
+#[test]
+fn test_chunks_in_aabb() {
+    let boundary = CHUNK_SIZE / 2.;
+    assert_eq!(
+        vec![ChunkPosition::new(0, 0, 0), ChunkPosition::new(1, 0, 0)],
+        chunks_in_aabb(
+            Vec3::new(boundary - 1., -1., -1.),
+            Vec3::new(boundary + 1., 1., 1.)
+        )
+    );
+    assert_eq!(
+        vec![ChunkPosition::new(0, 0, 0)],
+        chunks_in_aabb(Vec3::splat(-1.), Vec3::splat(1.))
+    );
+
+    // Chunks outside of the world are skipped
+    let edge = WORLD_MAX_CHUNK as f32 * CHUNK_SIZE;
+    assert_eq!(
+        8,
+        chunks_in_aabb(Vec3::splat(edge - boundary - 1.), Vec3::splat(edge + 100.)).len()
+    );
+    assert!(chunks_in_aabb(Vec3::splat(1e9), Vec3::splat(2e9)).is_empty());
+}
+
+#[test]
+fn test_inline_capacity() {
+    const { assert!(INLINE_VOXELS >= 3) };
+    let Encoding::Voxels(buf) = ChunkData::air().0 else {
+        panic!("Expected voxel encoding");
+    };
+    assert!(!buf.spilled());
+
+    // A flat terrain chunk with a handful of runs
+    let mut chunk = RawChunk::air();
+    for y in 0..4 {
+        chunk.fill_column(y, 0, &[Voxel::new(1, Voxel::MAX_VALUE); 8]);
+    }
+    let data = ChunkData::from(&chunk);
+    let Encoding::Voxels(buf) = &data.0 else {
+        panic!("Expected voxel encoding");
+    };
+    assert_eq!(buf.len() > INLINE_VOXELS, buf.spilled());
+    assert_eq!(chunk.0, data.expand().0);
+    let json = serde_json::to_string(&data).unwrap();
+    assert_eq!(data.0, serde_json::from_str::<ChunkData>(&json).unwrap().0);
 }
@@ -2,16 +2,24 @@
 //! It uses the fast_surface_nets crate to generate meshes
 
 use crate::{
-    ChunkData, ChunkMap, ChunkPosition, RawChunk, Voxel, CHUNK_BOUNDS, CHUNK_SIDES, CHUNK_SIZE,
-    VOXEL_SIZE,
+    storage::StableHasher, ChunkData, ChunkMap, ChunkPosition, RawChunk, Voxel, VoxelSize,
+    CHUNK_BOUNDS, CHUNK_SIDES,
 };
 
-use bevy::prelude::{Deref, DerefMut, Query};
+use bevy::{
+    prelude::{Deref, DerefMut, IVec3, Query, UVec3, Vec3},
+    utils::HashMap,
+};
 pub use fast_surface_nets::SurfaceNetsBuffer;
 use fast_surface_nets::{
     ndshape::{ConstShape3u32, ConstShape3u8, Shape},
-    surface_nets,
+    surface_nets, SignedDistance,
 };
+use std::hash::Hasher;
+
+/// The number of voxels the meshing grid reads from each neighboring chunk, on every side of the
+/// meshed chunk. Surface nets needs one layer to close the seams between chunks
+pub const OVERLAP: u32 = 1;
 
 /// Data about surrounding chunks of data
 #[derive(Default)]
@@ -19,7 +27,8 @@ pub struct SurroundingChunks([Option<RawChunk>; 3 * 3 * 3]);
 
 impl SurroundingChunks {
     const SHAPE: ConstShape3u8<3, 3, 3> = ConstShape3u8::<3, 3, 3>;
-    const LAST_CHUNK: i32 = CHUNK_SIDES as i32 + 1;
+    /// The first grid coordinate that lies in the next chunk
+    const LAST_CHUNK: i32 = (CHUNK_BOUNDS + OVERLAP) as i32;
 
     fn clear(&mut self) {
         for chunk in &mut self.0 {
@@ -27,10 +36,64 @@ impl SurroundingChunks {
         }
     }
 
-    /// Get the voxel at the specified coordinates
+    /// Expand the chunk at chunk_pos and its 26 neighbors, replacing the previous data. Missing
+    /// chunks are treated as air
+    pub fn fill(
+        &mut self,
+        chunk_pos: ChunkPosition,
+        chunk_map: &ChunkMap,
+        query: &Query<&ChunkData>,
+    ) {
+        self.clear();
+        for i in 0..Self::SHAPE.usize() {
+            let [x, y, z] = Self::SHAPE.delinearize(i as u8);
+            let desired_pos = chunk_pos + [-1 + x as i8, -1 + y as i8, -1 + z as i8];
+            let Some(chunk_entity) = chunk_map.get(&desired_pos) else {
+                continue;
+            };
+            let Ok(chunk) = query.get(*chunk_entity) else {
+                continue;
+            };
+            self.0[i] = Some(chunk.expand());
+        }
+    }
+
+    /// Place an expanded chunk at the offset from the center chunk, where every axis is in the
+    /// range -1..=1. This allows reusing expanded chunks when meshing neighboring chunks, or
+    /// meshing chunks that are not stored, like a preview of an edit. Offsets without a chunk are
+    /// treated as air
+    ///
+    /// # Panics
+    /// Panics if the offset is outside of the -1..=1 range
+    pub fn set_chunk(&mut self, offset: [i8; 3], chunk: RawChunk) {
+        self.0[Self::offset_index(offset)] = Some(chunk);
+    }
+
+    /// Place the chunk that is meshed, the same as [Self::set_chunk] with an offset of 0
+    pub fn center(&mut self, chunk: RawChunk) {
+        self.set_chunk([0; 3], chunk);
+    }
+
+    /// Take the expanded chunk at the offset from the center chunk, leaving air in its place. See
+    /// [Self::set_chunk]
+    pub fn take_chunk(&mut self, offset: [i8; 3]) -> Option<RawChunk> {
+        self.0[Self::offset_index(offset)].take()
+    }
+
+    fn offset_index(offset: [i8; 3]) -> usize {
+        assert!(
+            offset.iter().all(|v| (-1..=1).contains(v)),
+            "neighbor offset {offset:?} is outside of the -1..=1 range"
+        );
+        Self::SHAPE.linearize(offset.map(|v| (v + 1) as u8)) as usize
+    }
+
+    /// Get the voxel at the specified grid coordinates, where the voxels of the center chunk start
+    /// at [OVERLAP]
     pub fn get_voxel(&self, xyz: [i32; 3]) -> Voxel {
+        const FIRST: i32 = OVERLAP as i32;
         let mut iter = xyz.iter().map(|v| {
-            if *v <= 0 {
+            if *v < FIRST {
                 0
             } else if *v >= Self::LAST_CHUNK {
                 2
@@ -48,12 +111,12 @@ impl SurroundingChunks {
         let Some(ref chunk) = self.0[chunk_idx as usize] else {return Voxel::AIR;};
 
         let mut iter = xyz.iter().map(|v| {
-            (if *v <= 0 {
-                CHUNK_SIDES as i32 - 1 + v
+            (if *v < FIRST {
+                CHUNK_SIDES as i32 - FIRST + v
             } else if *v >= Self::LAST_CHUNK {
                 *v - Self::LAST_CHUNK
             } else {
-                v - 1
+                v - FIRST
             }) as u32
         });
 
@@ -66,7 +129,7 @@ impl SurroundingChunks {
 pub struct Grid(Vec<Voxel>);
 
 impl Grid {
-    const BOUNDS: u32 = CHUNK_BOUNDS + 2;
+    pub(crate) const BOUNDS: u32 = CHUNK_BOUNDS + 2 * OVERLAP;
     /// The shape of the grid
     pub const SHAPE: ConstShape3u32<{ Self::BOUNDS }, { Self::BOUNDS }, { Self::BOUNDS }> =
         ConstShape3u32::<{ Self::BOUNDS }, { Self::BOUNDS }, { Self::BOUNDS }>;
@@ -78,9 +141,39 @@ impl Default for Grid {
     }
 }
 
+/// Clear every Vec of the buffer, so no data of a previous mesh is left behind
+pub(crate) fn clear_buffer(buffer: &mut SurfaceNetsBuffer) {
+    buffer.positions.clear();
+    buffer.normals.clear();
+    buffer.indices.clear();
+    buffer.surface_points.clear();
+    buffer.surface_strides.clear();
+    buffer.stride_to_index.clear();
+}
+
+/// Clear the buffer, and shrink any of its Vecs that hold more than `max_capacity` elements.
+/// This keeps pooled buffers from holding on to the memory of a few unusually large meshes
+pub fn reset_buffer(buffer: &mut SurfaceNetsBuffer, max_capacity: usize) {
+    fn reset<T>(v: &mut Vec<T>, max_capacity: usize) {
+        v.clear();
+        if v.capacity() > max_capacity {
+            v.shrink_to(max_capacity);
+        }
+    }
+
+    reset(&mut buffer.positions, max_capacity);
+    reset(&mut buffer.normals, max_capacity);
+    reset(&mut buffer.indices, max_capacity);
+    reset(&mut buffer.surface_points, max_capacity);
+    reset(&mut buffer.surface_strides, max_capacity);
+    reset(&mut buffer.stride_to_index, max_capacity);
+}
+
 /// Generate the mesh for a chunk, which is returned as a Vec of vertices and a Vec of indices
 /// This function queries and expands the necessary chunk data itself and just needs the chunk map
-/// and position of the chunk that needs a mesh
+/// and position of the chunk that needs a mesh.
+/// Returns false when the chunk has no mesh. If the chunk and all its neighbors are uniform and on
+/// the same side of the surface, this is detected before any chunk is expanded
 pub fn generate_chunk(
     buffer: &mut SurfaceNetsBuffer,
     data: &mut SurroundingChunks,
@@ -88,18 +181,99 @@ pub fn generate_chunk(
     chunk_pos: ChunkPosition,
     chunk_map: &ChunkMap,
     query: &Query<&ChunkData>,
-) {
+) -> bool {
+    generate_chunk_sized(
+        buffer,
+        data,
+        grid,
+        chunk_pos,
+        chunk_map,
+        query,
+        VoxelSize::default(),
+    )
+}
+
+/// Generate the mesh for a chunk like [generate_chunk], but with voxels of the given
+/// [VoxelSize]. The mesh is centered on the chunk the same way, offset by half of
+/// [VoxelSize::chunk_size]
+pub fn generate_chunk_sized(
+    buffer: &mut SurfaceNetsBuffer,
+    data: &mut SurroundingChunks,
+    grid: &mut Grid,
+    chunk_pos: ChunkPosition,
+    chunk_map: &ChunkMap,
+    query: &Query<&ChunkData>,
+    voxel_size: VoxelSize,
+) -> bool {
     data.clear();
     grid.clear();
 
-    for i in 0..SurroundingChunks::SHAPE.usize() {
-        let [x, y, z] = SurroundingChunks::SHAPE.delinearize(i as u8);
-        let desired_pos = chunk_pos + [-1 + x as i8, -1 + y as i8, -1 + z as i8];
-        let Some(chunk_entity) = chunk_map.get(&desired_pos) else {continue;};
-        let Ok(chunk) = query.get(*chunk_entity) else {continue;};
-        data.0[i] = Some(chunk.expand());
+    if !has_surface(chunk_pos, chunk_map, query) {
+        clear_buffer(buffer);
+        return false;
     }
 
+    data.fill(chunk_pos, chunk_map, query);
+    mesh_grid(buffer, data, grid, voxel_size)
+}
+
+/// Generate the mesh for a chunk from chunks that were already expanded, see
+/// [SurroundingChunks::fill]. Callers that mesh a region can keep expanded chunks around to mesh
+/// their neighbors, instead of expanding every chunk up to 27 times.
+/// Returns false when the chunk has no mesh
+pub fn generate_chunk_from(
+    buffer: &mut SurfaceNetsBuffer,
+    data: &SurroundingChunks,
+    grid: &mut Grid,
+) -> bool {
+    mesh_grid(buffer, data, grid, VoxelSize::default())
+}
+
+pub(crate) fn mesh_grid(
+    buffer: &mut SurfaceNetsBuffer,
+    data: &SurroundingChunks,
+    grid: &mut Grid,
+    voxel_size: VoxelSize,
+) -> bool {
+    fill_grid(grid, data);
+    surface_nets(grid.as_slice(), &Grid::SHAPE, [0; 3], [Grid::BOUNDS - 1; 3], buffer);
+    to_chunk_space_sized(buffer, voxel_size);
+
+    !buffer.indices.is_empty()
+}
+
+/// Lazily mesh every chunk in the chunk map, yielding the position and mesh of each chunk that has
+/// a mesh. The chunk expansions and grid are reused between chunks, while every yielded buffer is
+/// owned by the caller. Chunks are visited in the iteration order of the map
+pub fn mesh_all<'a>(
+    chunk_map: &'a ChunkMap,
+    query: &'a Query<'a, 'a, &'static ChunkData>,
+) -> impl Iterator<Item = (ChunkPosition, SurfaceNetsBuffer)> + 'a {
+    let mut buffer = SurfaceNetsBuffer::default();
+    let mut data = SurroundingChunks::default();
+    let mut grid = Grid::default();
+    chunk_map.keys().filter_map(move |pos| {
+        if !generate_chunk(&mut buffer, &mut data, &mut grid, *pos, chunk_map, query) {
+            return None;
+        }
+        Some((*pos, clone_buffer(&buffer)))
+    })
+}
+
+fn clone_buffer(buffer: &SurfaceNetsBuffer) -> SurfaceNetsBuffer {
+    SurfaceNetsBuffer {
+        positions: buffer.positions.clone(),
+        normals: buffer.normals.clone(),
+        indices: buffer.indices.clone(),
+        surface_points: buffer.surface_points.clone(),
+        surface_strides: buffer.surface_strides.clone(),
+        stride_to_index: buffer.stride_to_index.clone(),
+    }
+}
+
+/// Fill the grid with the voxels of the chunk and the borders of its neighbors
+pub(crate) fn fill_grid(grid: &mut Grid, data: &SurroundingChunks) {
+    grid.clear();
     for i in 0..Grid::SHAPE.usize() {
         let xyz = Grid::SHAPE.delinearize(i as u32);
         grid.push(data.get_voxel([
@@ -108,11 +282,1080 @@ pub fn generate_chunk(
             xyz[2] as i32,
         ]));
     }
+}
+
+/// Convert the positions in the buffer from grid coordinates to positions relative to the chunk.
+/// Grid coordinate `OVERLAP - 1` lies at `-CHUNK_SIZE / 2`
+#[cfg(feature = "marching-cubes")]
+pub(crate) fn to_chunk_space(buffer: &mut SurfaceNetsBuffer) {
+    to_chunk_space_sized(buffer, VoxelSize::default());
+}
 
-    surface_nets(grid.as_slice(), &Grid::SHAPE, [0; 3], [(CHUNK_SIDES + 1) as u32; 3], buffer);
+/// Convert the positions in the buffer to positions relative to the chunk like [to_chunk_space],
+/// for voxels of the given size. Stretching the mesh also stretches its normals, so they are
+/// corrected when the voxels aren't cubes
+fn to_chunk_space_sized(buffer: &mut SurfaceNetsBuffer, voxel_size: VoxelSize) {
+    const SHIFT: f32 = (OVERLAP - 1) as f32;
+    let offset = voxel_size.chunk_size() / 2.;
     for pos in buffer.positions.iter_mut() {
-        pos[0] = pos[0] * VOXEL_SIZE - CHUNK_SIZE / 2.;
-        pos[1] = pos[1] * VOXEL_SIZE - CHUNK_SIZE / 2.;
-        pos[2] = pos[2] * VOXEL_SIZE - CHUNK_SIZE / 2.;
+        *pos = ((Vec3::from(*pos) - SHIFT) * *voxel_size - offset).to_array();
+    }
+    if voxel_size.x != voxel_size.y || voxel_size.x != voxel_size.z {
+        for normal in buffer.normals.iter_mut() {
+            *normal = (Vec3::from(*normal) / *voxel_size)
+                .normalize_or_zero()
+                .to_array();
+        }
     }
 }
+
+/// Generate the mesh for a chunk like [generate_chunk], and fill `materials` with a material for
+/// every vertex. The material of a vertex is the material of the most solid of the 8 voxels at the
+/// corners of the cell the vertex lies in, ties are broken toward the lowest material id. Since
+/// this only depends on those 8 voxels, vertices on the border between chunks get the same
+/// material in both chunk meshes
+pub fn generate_chunk_with_materials(
+    buffer: &mut SurfaceNetsBuffer,
+    data: &mut SurroundingChunks,
+    grid: &mut Grid,
+    chunk_pos: ChunkPosition,
+    chunk_map: &ChunkMap,
+    query: &Query<&ChunkData>,
+    materials: &mut Vec<u8>,
+) -> bool {
+    materials.clear();
+    if !generate_chunk(buffer, data, grid, chunk_pos, chunk_map, query) {
+        return false;
+    }
+    materials.extend(
+        buffer
+            .surface_points
+            .iter()
+            .map(|corner| vertex_material(grid, *corner)),
+    );
+    true
+}
+
+/// Get the material of the most solid voxel of the cell with the specified minimum corner
+fn vertex_material(grid: &Grid, [x, y, z]: [u32; 3]) -> u8 {
+    (0..8)
+        .map(|i| {
+            grid[Grid::SHAPE.linearize([x + (i & 1), y + ((i >> 1) & 1), z + (i >> 2)]) as usize]
+        })
+        .min_by_key(|voxel| (std::cmp::Reverse(voxel.value()), voxel.material()))
+        .map_or(0, |voxel| voxel.material())
+}
+
+/// Generate the mesh for a chunk like [generate_chunk], but with flat shading. See [flat_shade]
+pub fn generate_chunk_flat(
+    buffer: &mut SurfaceNetsBuffer,
+    data: &mut SurroundingChunks,
+    grid: &mut Grid,
+    chunk_pos: ChunkPosition,
+    chunk_map: &ChunkMap,
+    query: &Query<&ChunkData>,
+) -> bool {
+    let has_mesh = generate_chunk(buffer, data, grid, chunk_pos, chunk_map, query);
+    flat_shade(buffer);
+    has_mesh
+}
+
+/// Generate the mesh for a chunk like [generate_chunk], but with normals from the gradient of the
+/// signed distance field. See [gradient_normals]
+pub fn generate_chunk_gradient_normals(
+    buffer: &mut SurfaceNetsBuffer,
+    data: &mut SurroundingChunks,
+    grid: &mut Grid,
+    chunk_pos: ChunkPosition,
+    chunk_map: &ChunkMap,
+    query: &Query<&ChunkData>,
+) -> bool {
+    let has_mesh = generate_chunk(buffer, data, grid, chunk_pos, chunk_map, query);
+    gradient_normals(buffer, data);
+    has_mesh
+}
+
+/// Replace the normals of a mesh with the gradient of the signed distance field, interpolated
+/// between the central differences at the corners of the cell each vertex lies in. The voxels are
+/// read from the surrounding chunks instead of the grid, so vertices on the border between chunks
+/// get the same normal in both chunk meshes
+pub fn gradient_normals(buffer: &mut SurfaceNetsBuffer, data: &SurroundingChunks) {
+    gradient_normals_sized(buffer, data, VoxelSize::default());
+}
+
+/// Replace the normals of a mesh like [gradient_normals], for a mesh made with voxels of the given
+/// [VoxelSize], see [generate_chunk_sized]
+pub fn gradient_normals_sized(
+    buffer: &mut SurfaceNetsBuffer,
+    data: &SurroundingChunks,
+    voxel_size: VoxelSize,
+) {
+    let value = |p: IVec3| f32::from(data.get_voxel(p.to_array()));
+    let gradient = |p: IVec3| {
+        Vec3::new(
+            value(p + IVec3::X) - value(p - IVec3::X),
+            value(p + IVec3::Y) - value(p - IVec3::Y),
+            value(p + IVec3::Z) - value(p - IVec3::Z),
+        )
+    };
+
+    let vertices = buffer.positions.iter().zip(buffer.surface_points.iter());
+    for (normal, (pos, corner)) in buffer.normals.iter_mut().zip(vertices) {
+        let corner = UVec3::from(*corner).as_ivec3();
+        let grid_pos =
+            (Vec3::from(*pos) + voxel_size.chunk_size() / 2.) / *voxel_size + (OVERLAP - 1) as f32;
+        let t = (grid_pos - corner.as_vec3()).clamp(Vec3::ZERO, Vec3::ONE);
+
+        let mut sum = Vec3::ZERO;
+        for i in 0..8 {
+            let offset = IVec3::new(i & 1, (i >> 1) & 1, (i >> 2) & 1);
+            let weight = Vec3::select(offset.cmpeq(IVec3::ONE), t, Vec3::ONE - t);
+            sum += weight.x * weight.y * weight.z * gradient(corner + offset);
+        }
+        // The differences are per voxel, which are only the same as per meter for cubes
+        *normal = (sum / *voxel_size).normalize_or_zero().to_array();
+    }
+}
+
+/// Turn a smooth mesh into a flat shaded one. Every triangle gets its own copy of its vertices,
+/// with the normal of the triangle, and the indices become sequential
+pub fn flat_shade(buffer: &mut SurfaceNetsBuffer) {
+    let mut positions = Vec::with_capacity(buffer.indices.len());
+    let mut normals = Vec::with_capacity(buffer.indices.len());
+    for triangle in buffer.indices.chunks_exact(3) {
+        let [a, b, c] = [0, 1, 2].map(|i| Vec3::from(buffer.positions[triangle[i] as usize]));
+        let mut normal = (b - a).cross(c - a).normalize_or_zero();
+        // Keep the normal on the same side as the smooth normals
+        let smooth: Vec3 = triangle
+            .iter()
+            .map(|i| Vec3::from(buffer.normals[*i as usize]))
+            .sum();
+        if normal.dot(smooth) < 0. {
+            normal = -normal;
+        }
+        for pos in [a, b, c] {
+            positions.push(pos.to_array());
+            normals.push(normal.to_array());
+        }
+    }
+
+    buffer.indices.clear();
+    buffer.indices.extend(0..positions.len() as u32);
+    buffer.positions = positions;
+    buffer.normals = normals;
+}
+
+/// Reduce the number of triangles of a mesh by collapsing vertices in near-coplanar regions into
+/// one of their neighbors, as long as the surface stays within `error` meters of the original
+/// surface. Large flat areas end up with far fewer triangles.
+/// Vertices in the overlap cells at the border of the grid are never removed, along with vertices
+/// on the edge of the mesh, so the seams with neighboring chunks still line up. The buffer must
+/// come straight from surface nets, since its cells are used to find these vertices
+pub fn simplify(buffer: &mut SurfaceNetsBuffer, error: f32) {
+    let vertices = buffer.positions.len();
+    let positions: Vec<Vec3> = buffer.positions.iter().map(|p| Vec3::from(*p)).collect();
+    let position = |v: u32| positions[v as usize];
+    let mut triangles: Vec<Option<[u32; 3]>> = buffer
+        .indices
+        .chunks_exact(3)
+        .map(|t| Some([t[0], t[1], t[2]]))
+        .collect();
+    let mut faces = vec![Vec::new(); vertices];
+    let mut edges = HashMap::<(u32, u32), u32>::default();
+    for (i, triangle) in triangles.iter().enumerate() {
+        let triangle = triangle.unwrap();
+        for (j, &v) in triangle.iter().enumerate() {
+            faces[v as usize].push(i);
+            let next = triangle[(j + 1) % 3];
+            *edges.entry((v.min(next), v.max(next))).or_default() += 1;
+        }
+    }
+
+    let mut locked: Vec<bool> = buffer
+        .surface_points
+        .iter()
+        .map(|cell| cell.iter().any(|c| *c == 0 || *c >= Grid::BOUNDS - 2))
+        .collect();
+    for ((a, b), _) in edges.iter().filter(|(_, count)| **count != 2) {
+        locked[*a as usize] = true;
+        locked[*b as usize] = true;
+    }
+
+    // How far the surface around each vertex may already be from the original surface
+    let mut deviation = vec![0f32; vertices];
+    let mut removed = vec![false; vertices];
+    let ring = |triangles: &[Option<[u32; 3]>], faces: &[usize], v: u32| {
+        let mut ring: Vec<u32> = faces
+            .iter()
+            .filter_map(|t| triangles[*t])
+            .flatten()
+            .filter(|n| *n != v)
+            .collect();
+        ring.sort_unstable();
+        ring.dedup();
+        ring
+    };
+
+    let mut collapsed = true;
+    while collapsed {
+        collapsed = false;
+        for v in 0..vertices as u32 {
+            if locked[v as usize] || removed[v as usize] {
+                continue;
+            }
+            faces[v as usize].retain(|t| triangles[*t].is_some());
+            let neighbors = ring(&triangles, &faces[v as usize], v);
+            let pos = position(v);
+            let normal = |t: [u32; 3]| {
+                let [a, b, c] = t.map(position);
+                (b - a).cross(c - a)
+            };
+            let plane = faces[v as usize]
+                .iter()
+                .map(|t| normal(triangles[*t].unwrap()))
+                .sum::<Vec3>()
+                .normalize_or_zero();
+            if plane == Vec3::ZERO {
+                continue;
+            }
+            let distance = neighbors
+                .iter()
+                .map(|n| (position(*n) - pos).dot(plane).abs())
+                .fold(0., f32::max);
+            let total = distance
+                + neighbors
+                    .iter()
+                    .map(|n| deviation[*n as usize])
+                    .fold(deviation[v as usize], f32::max);
+            if total > error {
+                continue;
+            }
+
+            let target = neighbors.iter().copied().find(|u| {
+                faces[*u as usize].retain(|t| triangles[*t].is_some());
+                // Collapsing an edge with more than two shared neighbors would fold the mesh
+                let shared = ring(&triangles, &faces[*u as usize], *u)
+                    .iter()
+                    .filter(|n| neighbors.binary_search(*n).is_ok())
+                    .count();
+                shared == 2
+                    && faces[v as usize].iter().all(|t| {
+                        let triangle = triangles[*t].unwrap();
+                        if triangle.contains(u) {
+                            return true;
+                        }
+                        let moved = triangle.map(|i| if i == v { *u } else { i });
+                        let (before, after) = (normal(triangle), normal(moved));
+                        after.dot(before) > 0. && after.length_squared() > 1e-12
+                    })
+            });
+            let Some(u) = target else {
+                continue;
+            };
+
+            for t in std::mem::take(&mut faces[v as usize]) {
+                let triangle = triangles[t].unwrap();
+                if triangle.contains(&u) {
+                    triangles[t] = None;
+                } else {
+                    triangles[t] = Some(triangle.map(|i| if i == v { u } else { i }));
+                    faces[u as usize].push(t);
+                }
+            }
+            removed[v as usize] = true;
+            for n in neighbors {
+                deviation[n as usize] = deviation[n as usize].max(total);
+            }
+            collapsed = true;
+        }
+    }
+
+    // Compact the remaining vertices, keeping them in the same order
+    let mut remap = vec![u32::MAX; vertices];
+    let mut next = 0;
+    for (v, removed) in removed.iter().enumerate() {
+        if !removed {
+            remap[v] = next;
+            next += 1;
+        }
+    }
+    let keep = |v: &usize| !removed[*v];
+    buffer.positions = (0..vertices)
+        .filter(keep)
+        .map(|v| buffer.positions[v])
+        .collect();
+    buffer.normals = (0..vertices)
+        .filter(keep)
+        .map(|v| buffer.normals[v])
+        .collect();
+    buffer.surface_points = (0..vertices)
+        .filter(keep)
+        .map(|v| buffer.surface_points[v])
+        .collect();
+    buffer.surface_strides = (0..vertices)
+        .filter(keep)
+        .map(|v| buffer.surface_strides[v])
+        .collect();
+    for index in buffer.stride_to_index.iter_mut() {
+        if let Some(v) = remap.get(*index as usize) {
+            *index = *v;
+        }
+    }
+    buffer.indices.clear();
+    buffer.indices.extend(
+        triangles
+            .iter()
+            .flatten()
+            .flatten()
+            .map(|v| remap[*v as usize]),
+    );
+}
+
+/// The error returned when a mesh has too many vertices to be indexed with u16 indices
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IndexOverflow {
+    /// The number of vertices in the mesh
+    pub vertices: usize,
+}
+
+impl std::fmt::Display for IndexOverflow {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "mesh has {} vertices, which can't be indexed with u16 indices",
+            self.vertices
+        )
+    }
+}
+
+impl std::error::Error for IndexOverflow {}
+
+/// Generate the mesh for a chunk like [generate_chunk], but return the positions along with u16
+/// indices. Returns an error if the mesh has more than [u16::MAX] vertices
+pub fn generate_chunk_u16(
+    buffer: &mut SurfaceNetsBuffer,
+    data: &mut SurroundingChunks,
+    grid: &mut Grid,
+    chunk_pos: ChunkPosition,
+    chunk_map: &ChunkMap,
+    query: &Query<&ChunkData>,
+) -> Result<(Vec<[f32; 3]>, Vec<u16>), IndexOverflow> {
+    generate_chunk(buffer, data, grid, chunk_pos, chunk_map, query);
+    u16_indices(buffer)
+}
+
+fn u16_indices(buffer: &SurfaceNetsBuffer) -> Result<(Vec<[f32; 3]>, Vec<u16>), IndexOverflow> {
+    if buffer.positions.len() > u16::MAX as usize {
+        return Err(IndexOverflow {
+            vertices: buffer.positions.len(),
+        });
+    }
+    Ok((
+        buffer.positions.clone(),
+        buffer.indices.iter().map(|i| *i as u16).collect(),
+    ))
+}
+
+/// Get a hash of the chunk and its 26 neighbors, which together determine the mesh of the chunk.
+/// When the hash is unchanged since the chunk was last meshed, the old mesh can be reused. Missing
+/// chunks are hashed as air, since that is how they are meshed
+pub fn neighborhood_hash(
+    chunk_pos: ChunkPosition,
+    chunk_map: &ChunkMap,
+    query: &Query<&ChunkData>,
+) -> u64 {
+    let air = ChunkData::air().content_hash();
+    let mut hasher = StableHasher::default();
+    for i in 0..SurroundingChunks::SHAPE.usize() {
+        let [x, y, z] = SurroundingChunks::SHAPE.delinearize(i as u8);
+        let desired_pos = chunk_pos + [-1 + x as i8, -1 + y as i8, -1 + z as i8];
+        let hash = match chunk_map.get(&desired_pos).map(|e| query.get(*e)) {
+            Some(Ok(chunk)) => chunk.content_hash(),
+            _ => air,
+        };
+        hasher.write_u64(hash);
+    }
+    hasher.finish()
+}
+
+/// Check if the chunk could have a surface, which is not the case if the chunk and all its
+/// neighbors are uniform and either all solid or all empty
+pub(crate) fn has_surface(
+    chunk_pos: ChunkPosition,
+    chunk_map: &ChunkMap,
+    query: &Query<&ChunkData>,
+) -> bool {
+    let mut negative = None;
+    for i in 0..SurroundingChunks::SHAPE.usize() {
+        let [x, y, z] = SurroundingChunks::SHAPE.delinearize(i as u8);
+        let desired_pos = chunk_pos + [-1 + x as i8, -1 + y as i8, -1 + z as i8];
+        let voxel = match chunk_map.get(&desired_pos).map(|e| query.get(*e)) {
+            Some(Ok(chunk)) => chunk.is_uniform(),
+            _ => Some(Voxel::AIR),
+        };
+        let Some(voxel) = voxel else {
+            return true;
+        };
+        if *negative.get_or_insert(voxel.is_negative()) != voxel.is_negative() {
+            return true;
+        }
+    }
+    false
+}
+
+#[test]
+fn test_grid_overlap() {
+    let voxel = |material| Voxel::new(material, Voxel::MAX_VALUE);
+    let mut data = SurroundingChunks::default();
+    let mut center = RawChunk::air();
+    center.set_voxel(0, 0, 0, voxel(1));
+    center.set_voxel(CHUNK_BOUNDS - 1, 0, 0, voxel(2));
+    data.center(center);
+    let mut low = RawChunk::air();
+    low.set_voxel(CHUNK_BOUNDS - OVERLAP, 0, 0, voxel(3));
+    data.set_chunk([-1, 0, 0], low);
+    let mut high = RawChunk::air();
+    high.set_voxel(OVERLAP - 1, 0, 0, voxel(4));
+    data.set_chunk([1, 0, 0], high);
+
+    let first = OVERLAP as i32;
+    let last = (CHUNK_BOUNDS + OVERLAP) as i32 - 1;
+    assert_eq!(voxel(1), data.get_voxel([first, first, first]));
+    assert_eq!(voxel(2), data.get_voxel([last, first, first]));
+    assert_eq!(voxel(3), data.get_voxel([0, first, first]));
+    assert_eq!(
+        voxel(4),
+        data.get_voxel([Grid::BOUNDS as i32 - 1, first, first])
+    );
+}
+
+#[test]
+fn test_reset_buffer() {
+    let mut buffer = SurfaceNetsBuffer::default();
+    buffer.positions.resize(10000, [0.; 3]);
+    buffer.indices.resize(30000, 0);
+    buffer.normals.resize(50, [0.; 3]);
+
+    reset_buffer(&mut buffer, 100);
+    assert!(buffer.positions.is_empty());
+    assert!(buffer.positions.capacity() < 10000);
+    assert!(buffer.indices.capacity() < 30000);
+    // Small Vecs keep their memory
+    assert!(buffer.normals.capacity() >= 50);
+}
+
+#[test]
+fn test_skip_empty_neighborhood() {
+    use bevy::{ecs::system::SystemState, prelude::World};
+
+    let mut world = World::default();
+    let mut chunk_map = ChunkMap::default();
+    for pos in [ChunkPosition::new(0, 0, 0), ChunkPosition::new(1, 0, 0)] {
+        let entity = world.spawn((pos, ChunkData::air())).id();
+        chunk_map.insert(pos, entity);
+    }
+
+    let mut state: SystemState<Query<&ChunkData>> = SystemState::new(&mut world);
+    let query = state.get(&world);
+
+    let mut buffer = SurfaceNetsBuffer::default();
+    let mut data = SurroundingChunks::default();
+    let mut grid = Grid::default();
+    assert!(!generate_chunk(
+        &mut buffer,
+        &mut data,
+        &mut grid,
+        ChunkPosition::new(0, 0, 0),
+        &chunk_map,
+        &query,
+    ));
+    assert!(grid.is_empty());
+    assert!(buffer.indices.is_empty());
+}
+
+#[test]
+fn test_u16_indices() {
+    let mut buffer = SurfaceNetsBuffer {
+        positions: vec![[0.; 3], [1., 0., 0.], [0., 1., 0.]],
+        indices: vec![0, 1, 2],
+        ..Default::default()
+    };
+    assert_eq!(
+        Ok((buffer.positions.clone(), vec![0, 1, 2])),
+        u16_indices(&buffer)
+    );
+
+    buffer.positions = vec![[0.; 3]; u16::MAX as usize + 1];
+    assert_eq!(
+        Err(IndexOverflow {
+            vertices: u16::MAX as usize + 1
+        }),
+        u16_indices(&buffer)
+    );
+}
+
+#[test]
+fn test_flat_shade() {
+    let mut buffer = SurfaceNetsBuffer {
+        positions: vec![[0., 0., 0.], [1., 0., 0.], [0., 0., 1.], [1., 1., 1.]],
+        normals: vec![[0., 1., 0.]; 4],
+        indices: vec![0, 2, 1, 1, 2, 3],
+        ..Default::default()
+    };
+
+    flat_shade(&mut buffer);
+    assert_eq!(6, buffer.positions.len());
+    assert_eq!(buffer.positions.len(), buffer.indices.len());
+    assert_eq!(buffer.positions.len(), buffer.normals.len());
+    assert_eq!(vec![0, 1, 2, 3, 4, 5], buffer.indices);
+    for triangle in buffer.normals.chunks_exact(3) {
+        assert!(triangle.iter().all(|n| *n == triangle[0]));
+    }
+    assert_eq!([0., 1., 0.], buffer.normals[0]);
+    assert_ne!(buffer.normals[0], buffer.normals[3]);
+}
+
+#[test]
+fn test_neighborhood_hash() {
+    use bevy::{ecs::system::SystemState, prelude::World};
+
+    let mut world = World::default();
+    let mut chunk_map = ChunkMap::default();
+    for pos in [ChunkPosition::new(0, 0, 0), ChunkPosition::new(1, 0, 0)] {
+        let entity = world.spawn((pos, ChunkData::air())).id();
+        chunk_map.insert(pos, entity);
+    }
+
+    let center = ChunkPosition::new(0, 0, 0);
+    let mut state: SystemState<Query<&ChunkData>> = SystemState::new(&mut world);
+    let hash = neighborhood_hash(center, &chunk_map, &state.get(&world));
+    assert_eq!(
+        hash,
+        neighborhood_hash(center, &chunk_map, &state.get(&world))
+    );
+
+    let mut chunk = RawChunk::air();
+    chunk.set_voxel(0, 5, 5, Voxel::new(1, Voxel::MAX_VALUE));
+    let neighbor = chunk_map[&ChunkPosition::new(1, 0, 0)];
+    *world.get_mut::<ChunkData>(neighbor).unwrap() = chunk.into();
+    assert_ne!(
+        hash,
+        neighborhood_hash(center, &chunk_map, &state.get(&world))
+    );
+}
+
+#[test]
+fn test_vertex_materials_reused_buffer() {
+    use bevy::{ecs::system::SystemState, prelude::World};
+
+    let mut world = World::default();
+    let mut chunk_map = ChunkMap::default();
+    let mut chunk = RawChunk::air();
+    for x in 0..CHUNK_BOUNDS {
+        for y in 0..10 {
+            for z in 0..CHUNK_BOUNDS {
+                chunk.set_voxel(x, y, z, Voxel::new(3, Voxel::MAX_VALUE));
+            }
+        }
+    }
+    let entity = world.spawn(ChunkData::from(chunk)).id();
+    chunk_map.insert(ChunkPosition::new(0, 0, 0), entity);
+
+    let mut state: SystemState<Query<&ChunkData>> = SystemState::new(&mut world);
+    let query = state.get(&world);
+    let mut buffer = SurfaceNetsBuffer::default();
+    let mut data = SurroundingChunks::default();
+    let mut grid = Grid::default();
+    let mut materials = Vec::new();
+    let mut mesh = |chunk_pos| {
+        generate_chunk_with_materials(
+            &mut buffer,
+            &mut data,
+            &mut grid,
+            chunk_pos,
+            &chunk_map,
+            &query,
+            &mut materials,
+        )
+    };
+
+    assert!(mesh(ChunkPosition::new(0, 0, 0)));
+    // The air chunk is skipped before meshing, which must not leave the previous surface behind
+    assert!(!mesh(ChunkPosition::new(5, 5, 5)));
+    assert!(buffer.positions.is_empty());
+    assert!(buffer.surface_points.is_empty());
+    assert!(materials.is_empty());
+}
+
+#[test]
+fn test_vertex_materials_match_across_chunks() {
+    use bevy::{ecs::system::SystemState, prelude::World};
+
+    // A floor split diagonally between two materials, which are equally solid
+    let mut world = World::default();
+    let mut chunk_map = ChunkMap::default();
+    for chunk_x in 0..2 {
+        let mut chunk = RawChunk::air();
+        for x in 0..CHUNK_BOUNDS {
+            for y in 0..10 {
+                for z in 0..CHUNK_BOUNDS {
+                    let material = if chunk_x * CHUNK_BOUNDS + x + z < 25 {
+                        2
+                    } else {
+                        1
+                    };
+                    chunk.set_voxel(x, y, z, Voxel::new(material, Voxel::MAX_VALUE));
+                }
+            }
+        }
+        let pos = ChunkPosition::new(chunk_x as i8, 0, 0);
+        let entity = world.spawn((pos, ChunkData::from(chunk))).id();
+        chunk_map.insert(pos, entity);
+    }
+
+    let mut state: SystemState<Query<&ChunkData>> = SystemState::new(&mut world);
+    let query = state.get(&world);
+    let mesh = |chunk_x| {
+        let mut buffer = SurfaceNetsBuffer::default();
+        let mut materials = Vec::new();
+        assert!(generate_chunk_with_materials(
+            &mut buffer,
+            &mut SurroundingChunks::default(),
+            &mut Grid::default(),
+            ChunkPosition::new(chunk_x, 0, 0),
+            &chunk_map,
+            &query,
+            &mut materials,
+        ));
+        assert_eq!(buffer.positions.len(), materials.len());
+        (buffer.surface_points, materials)
+    };
+
+    let (points_a, materials_a) = mesh(0);
+    let (points_b, materials_b) = mesh(1);
+    assert_eq!((points_a.clone(), materials_a.clone()), mesh(0));
+    assert!(materials_a.contains(&1) && materials_a.contains(&2));
+
+    // Cells on the border are meshed by both chunks and must get the same material
+    let mut shared = 0;
+    for (point, material) in points_a.iter().zip(materials_a.iter()) {
+        let [x, y, z] = *point;
+        let Some(other) = x
+            .checked_sub(CHUNK_BOUNDS)
+            .and_then(|x| points_b.iter().position(|p| *p == [x, y, z]))
+        else {
+            continue;
+        };
+        assert_eq!(*material, materials_b[other]);
+        shared += 1;
+    }
+    assert!(shared > 0);
+}
+
+#[test]
+fn test_marked_air_neighbors() {
+    use bevy::{ecs::system::SystemState, prelude::World};
+
+    let center = ChunkPosition::new(0, 0, 0);
+    let mut chunk = RawChunk::air();
+    for x in 5..15 {
+        for y in 0..CHUNK_BOUNDS {
+            chunk.set_voxel(x, y, 0, Voxel::new(1, Voxel::MAX_VALUE));
+        }
+    }
+    let chunk = ChunkData::from(chunk);
+
+    let mesh = |real_air: bool| {
+        let mut world = World::default();
+        let mut chunk_map = ChunkMap::default();
+        let entity = world.spawn((center, chunk.clone())).id();
+        chunk_map.insert(center, entity);
+        for pos in [ChunkPosition::new(0, 1, 0), ChunkPosition::new(0, 0, -1)] {
+            if real_air {
+                let entity = world.spawn((pos, ChunkData::air())).id();
+                chunk_map.insert(pos, entity);
+            } else {
+                chunk_map.mark_air(pos);
+            }
+        }
+
+        let mut state: SystemState<Query<&ChunkData>> = SystemState::new(&mut world);
+        let query = state.get(&world);
+        let mut buffer = SurfaceNetsBuffer::default();
+        assert!(generate_chunk(
+            &mut buffer,
+            &mut SurroundingChunks::default(),
+            &mut Grid::default(),
+            center,
+            &chunk_map,
+            &query,
+        ));
+        (buffer.positions, buffer.indices)
+    };
+
+    assert_eq!(mesh(true), mesh(false));
+}
+
+#[test]
+fn test_generate_chunk_from() {
+    use bevy::{ecs::system::SystemState, prelude::World};
+
+    let mut world = World::default();
+    let mut chunk_map = ChunkMap::default();
+    for (i, pos) in [ChunkPosition::new(0, 0, 0), ChunkPosition::new(0, 0, 1)]
+        .into_iter()
+        .enumerate()
+    {
+        let mut chunk = RawChunk::air();
+        for x in 0..CHUNK_BOUNDS {
+            for z in 0..CHUNK_BOUNDS {
+                chunk.set_voxel(x, i as u32 * 5 + 3, z, Voxel::new(1, Voxel::MAX_VALUE));
+            }
+        }
+        let entity = world.spawn((pos, ChunkData::from(chunk))).id();
+        chunk_map.insert(pos, entity);
+    }
+
+    let mut state: SystemState<Query<&ChunkData>> = SystemState::new(&mut world);
+    let query = state.get(&world);
+    let center = ChunkPosition::new(0, 0, 0);
+
+    let mut buffer = SurfaceNetsBuffer::default();
+    let mut data = SurroundingChunks::default();
+    let mut grid = Grid::default();
+    assert!(generate_chunk(
+        &mut buffer,
+        &mut data,
+        &mut grid,
+        center,
+        &chunk_map,
+        &query,
+    ));
+
+    // Expand the chunks by hand, like a caller reusing expansions would
+    let mut prefilled = SurroundingChunks::default();
+    for (offset, pos) in [
+        ([0, 0, 0], center),
+        ([0, 0, 1], ChunkPosition::new(0, 0, 1)),
+    ] {
+        let chunk = query.get(chunk_map[&pos]).unwrap().expand();
+        prefilled.set_chunk(offset, chunk);
+    }
+    let mut prefilled_buffer = SurfaceNetsBuffer::default();
+    assert!(generate_chunk_from(
+        &mut prefilled_buffer,
+        &prefilled,
+        &mut Grid::default(),
+    ));
+    assert_eq!(buffer.positions, prefilled_buffer.positions);
+    assert_eq!(buffer.indices, prefilled_buffer.indices);
+    assert!(prefilled.take_chunk([0, 0, 1]).is_some());
+}
+
+#[test]
+fn test_manual_neighborhood() {
+    // A solid block in the corner of the chunk, continued by the neighbor above it
+    let block = |y_range: std::ops::Range<u32>| {
+        let mut chunk = RawChunk::air();
+        for x in 2..6 {
+            for y in y_range.clone() {
+                for z in 2..6 {
+                    chunk.set_voxel(x, y, z, Voxel::new(1, Voxel::MAX_VALUE));
+                }
+            }
+        }
+        chunk
+    };
+
+    let mut data = SurroundingChunks::default();
+    data.center(block(15..CHUNK_BOUNDS));
+    let mut buffer = SurfaceNetsBuffer::default();
+    let faces_up = |buffer: &SurfaceNetsBuffer| {
+        buffer
+            .normals
+            .iter()
+            .any(|normal| Vec3::from(*normal).normalize_or_zero().y > 0.9)
+    };
+    assert!(generate_chunk_from(
+        &mut buffer,
+        &data,
+        &mut Grid::default()
+    ));
+    assert!(faces_up(&buffer));
+
+    // With the block continuing into the chunk above, the top of the block is no longer meshed
+    data.set_chunk([0, 1, 0], block(0..5));
+    assert!(generate_chunk_from(
+        &mut buffer,
+        &data,
+        &mut Grid::default()
+    ));
+    assert!(!faces_up(&buffer));
+    assert!(data.take_chunk([0, 1, 0]).is_some());
+    assert!(data.take_chunk([0, 1, 0]).is_none());
+}
+
+#[test]
+fn test_gradient_normals_match_across_border() {
+    use crate::{CHUNK_SIZE, VOXEL_SIZE};
+
+    // A sphere on the border between two chunks along the x axis
+    let sphere = |chunk_x: f32| {
+        let mut chunk = RawChunk::air();
+        for x in 0..CHUNK_BOUNDS {
+            for y in 0..CHUNK_BOUNDS {
+                for z in 0..CHUNK_BOUNDS {
+                    let pos = (Vec3::new(x as f32, y as f32, z as f32) + 1.) * VOXEL_SIZE
+                        - CHUNK_SIZE / 2.
+                        + Vec3::X * chunk_x * CHUNK_SIZE;
+                    let distance = pos.distance(Vec3::new(CHUNK_SIZE / 2., 0.3, -0.2)) - 4.;
+                    chunk.set_voxel(x, y, z, Voxel::AIR.with_value_f32(distance.clamp(-1., 1.)));
+                }
+            }
+        }
+        chunk
+    };
+    let mesh = |neighbor: [i8; 3], chunk_x: f32| {
+        let mut data = SurroundingChunks::default();
+        data.center(sphere(chunk_x));
+        data.set_chunk(neighbor, sphere(chunk_x + neighbor[0] as f32));
+        let mut buffer = SurfaceNetsBuffer::default();
+        assert!(generate_chunk_from(
+            &mut buffer,
+            &data,
+            &mut Grid::default()
+        ));
+        gradient_normals(&mut buffer, &data);
+        buffer
+    };
+
+    let low = mesh([1, 0, 0], 0.);
+    let high = mesh([-1, 0, 0], 1.);
+    let mut shared = 0;
+    for (pos, normal) in low.positions.iter().zip(low.normals.iter()) {
+        let pos = Vec3::from(*pos) - Vec3::X * CHUNK_SIZE;
+        let Some(i) = high
+            .positions
+            .iter()
+            .position(|other| Vec3::from(*other).distance(pos) < 1e-3)
+        else {
+            continue;
+        };
+        shared += 1;
+        assert!(Vec3::from(*normal).dot(Vec3::from(high.normals[i])) > 0.999);
+    }
+    assert!(shared > 0);
+}
+
+#[test]
+#[should_panic]
+fn test_neighbor_offset_out_of_range() {
+    SurroundingChunks::default().set_chunk([2, 0, 0], RawChunk::air());
+}
+
+#[test]
+fn test_mesh_all() {
+    use bevy::{ecs::system::SystemState, prelude::World};
+
+    let mut world = World::default();
+    let mut chunk_map = ChunkMap::default();
+    let floor = || {
+        let mut chunk = RawChunk::air();
+        for x in 0..CHUNK_BOUNDS {
+            for z in 0..CHUNK_BOUNDS {
+                chunk.set_voxel(x, 3, z, Voxel::new(1, Voxel::MAX_VALUE));
+            }
+        }
+        ChunkData::from(chunk)
+    };
+    let chunks = [
+        (ChunkPosition::new(0, 0, 0), floor()),
+        (ChunkPosition::new(5, 0, 0), floor()),
+        (ChunkPosition::new(-5, 0, 0), ChunkData::air()),
+    ];
+    for (pos, chunk) in chunks {
+        let entity = world.spawn((pos, chunk)).id();
+        chunk_map.insert(pos, entity);
+    }
+
+    let mut state: SystemState<Query<&ChunkData>> = SystemState::new(&mut world);
+    let query = state.get(&world);
+
+    let mut meshed: Vec<_> = mesh_all(&chunk_map, &query)
+        .map(|(pos, mesh)| {
+            assert!(!mesh.indices.is_empty());
+            pos
+        })
+        .collect();
+    meshed.sort();
+    assert_eq!(
+        vec![ChunkPosition::new(0, 0, 0), ChunkPosition::new(5, 0, 0)],
+        meshed
+    );
+}
+
+#[test]
+fn test_generate_chunk_sized() {
+    use crate::CHUNK_SIZE;
+    use bevy::{ecs::system::SystemState, prelude::World};
+
+    let mut world = World::default();
+    let mut chunk_map = ChunkMap::default();
+    let mut chunk = RawChunk::air();
+    for x in 5..10 {
+        for y in 4..12 {
+            for z in 6..9 {
+                chunk.set_voxel(x, y, z, Voxel::new(1, Voxel::MAX_VALUE));
+            }
+        }
+    }
+    let entity = world.spawn(ChunkData::from(chunk)).id();
+    chunk_map.insert(ChunkPosition::new(0, 0, 0), entity);
+
+    let mut state: SystemState<Query<&ChunkData>> = SystemState::new(&mut world);
+    let query = state.get(&world);
+
+    let mut cubes = SurfaceNetsBuffer::default();
+    let mut tall = SurfaceNetsBuffer::default();
+    let mut data = SurroundingChunks::default();
+    let mut grid = Grid::default();
+    let pos = ChunkPosition::new(0, 0, 0);
+    assert!(generate_chunk(
+        &mut cubes, &mut data, &mut grid, pos, &chunk_map, &query
+    ));
+    let voxel_size = VoxelSize(Vec3::new(0.75, 1.5, 0.75));
+    assert!(generate_chunk_sized(
+        &mut tall, &mut data, &mut grid, pos, &chunk_map, &query, voxel_size,
+    ));
+
+    assert_eq!(cubes.indices, tall.indices);
+    assert_eq!(cubes.positions.len(), tall.positions.len());
+    for (cube, tall) in cubes.positions.iter().zip(tall.positions.iter()) {
+        let cube = Vec3::from(*cube) + CHUNK_SIZE / 2.;
+        let tall = Vec3::from(*tall) + voxel_size.chunk_size() / 2.;
+        assert!(tall.abs_diff_eq(cube * Vec3::new(1., 2., 1.), 1e-4));
+    }
+
+    let height = |buffer: &SurfaceNetsBuffer| {
+        let ys = buffer.positions.iter().map(|p| p[1]);
+        ys.clone().fold(f32::MIN, f32::max) - ys.fold(f32::MAX, f32::min)
+    };
+    assert!((height(&tall) - 2. * height(&cubes)).abs() < 1e-4);
+}
+
+#[test]
+fn test_gradient_normals_sized() {
+    // A tilted plane sampled with voxels that aren't cubes
+    let voxel_size = VoxelSize(Vec3::new(0.25, 0.5, 0.375));
+    let normal = Vec3::new(1., 1., 0.).normalize();
+    let mut chunk = RawChunk::air();
+    for x in 0..CHUNK_BOUNDS {
+        for y in 0..CHUNK_BOUNDS {
+            for z in 0..CHUNK_BOUNDS {
+                let pos = (UVec3::new(x, y, z) + 1).as_vec3() * *voxel_size
+                    - voxel_size.chunk_size() / 2.;
+                let distance = pos.dot(normal) - 0.3;
+                let voxel = Voxel::new(1, 0).with_value_f32(distance.clamp(-1., 1.));
+                chunk.set_voxel(x, y, z, voxel);
+            }
+        }
+    }
+    let mut data = SurroundingChunks::default();
+    data.center(chunk);
+    let mut buffer = SurfaceNetsBuffer::default();
+    assert!(mesh_grid(
+        &mut buffer,
+        &data,
+        &mut Grid::default(),
+        voxel_size
+    ));
+    gradient_normals_sized(&mut buffer, &data, voxel_size);
+
+    // Away from the air around the chunk the normals are those of the plane
+    let inner = voxel_size.chunk_size() / 2. - *voxel_size * 3.;
+    let mut checked = 0;
+    for (pos, n) in buffer.positions.iter().zip(buffer.normals.iter()) {
+        if Vec3::from(*pos).abs().cmplt(inner).all() {
+            assert!(Vec3::from(*n).dot(normal) > 0.999, "{n:?}");
+            checked += 1;
+        }
+    }
+    assert!(checked > 0);
+}
+
+#[test]
+fn test_simplify() {
+    use crate::{CHUNK_SIZE, VOXEL_SIZE};
+    use bevy::{ecs::system::SystemState, prelude::World};
+
+    // Flat ground continuing into the neighboring chunks, with solid chunks below it
+    let mut world = World::default();
+    let mut chunk_map = ChunkMap::default();
+    for x in -1..=1 {
+        for z in -1..=1 {
+            let mut chunk = RawChunk::air();
+            for x in 0..CHUNK_BOUNDS {
+                for y in 0..4 {
+                    for z in 0..CHUNK_BOUNDS {
+                        chunk.set_voxel(x, y, z, Voxel::new(1, Voxel::MAX_VALUE));
+                    }
+                }
+            }
+            let entity = world.spawn(ChunkData::from(chunk)).id();
+            chunk_map.insert(ChunkPosition::new(x, 0, z), entity);
+            let solid = RawChunk(vec![Voxel::new(1, Voxel::MAX_VALUE); crate::CHUNK_VOXELS]);
+            let entity = world.spawn(ChunkData::from(solid)).id();
+            chunk_map.insert(ChunkPosition::new(x, -1, z), entity);
+        }
+    }
+
+    let mut state: SystemState<Query<&ChunkData>> = SystemState::new(&mut world);
+    let query = state.get(&world);
+
+    let mut buffer = SurfaceNetsBuffer::default();
+    assert!(generate_chunk(
+        &mut buffer,
+        &mut SurroundingChunks::default(),
+        &mut Grid::default(),
+        ChunkPosition::new(0, 0, 0),
+        &chunk_map,
+        &query,
+    ));
+    let bounds = |buffer: &SurfaceNetsBuffer| {
+        let positions = buffer.positions.iter().map(|p| Vec3::from(*p));
+        (
+            positions.clone().fold(Vec3::MAX, Vec3::min),
+            positions.fold(Vec3::MIN, Vec3::max),
+        )
+    };
+    let border = |buffer: &SurfaceNetsBuffer| {
+        let mut border: Vec<_> = buffer
+            .positions
+            .iter()
+            .zip(buffer.surface_points.iter())
+            .filter(|(_, cell)| cell.iter().any(|c| *c == 0 || *c >= Grid::BOUNDS - 2))
+            .map(|(pos, _)| pos.map(f32::to_bits))
+            .collect();
+        border.sort_unstable();
+        border
+    };
+
+    let triangles = buffer.indices.len() / 3;
+    let (before_bounds, before_border) = (bounds(&buffer), border(&buffer));
+    simplify(&mut buffer, 0.01);
+
+    assert!(buffer.indices.len() / 3 < triangles / 4);
+    assert_eq!(before_bounds, bounds(&buffer));
+    assert_eq!(before_border, border(&buffer));
+    assert_eq!(buffer.positions.len(), buffer.normals.len());
+    assert!(buffer
+        .indices
+        .iter()
+        .all(|i| (*i as usize) < buffer.positions.len()));
+    // The ground is still at the same height
+    let ground = 4.5 * VOXEL_SIZE - CHUNK_SIZE / 2.;
+    assert!(buffer
+        .positions
+        .iter()
+        .all(|p| (p[1] - ground).abs() < 0.01));
+}
@@ -1,8 +1,27 @@
+use serde::{Deserialize, Deserializer, Serialize};
+
 /// A Voxel is the data for a single voxel. It holds a material type and a value. The value is used
 /// as a Signed Distance Field to create a smooth mesh.
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(transparent)]
 pub struct Voxel(u16);
 
+impl Default for Voxel {
+    fn default() -> Self {
+        Self::AIR
+    }
+}
+
+impl<'de> Deserialize<'de> for Voxel {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let raw = u16::deserialize(deserializer)?;
+        Ok(Self::new(
+            (raw >> Self::VALUE_BITS) as u8 & Self::MAX_MATERIAL,
+            raw & Self::VALUE_MASK,
+        ))
+    }
+}
+
 impl PartialEq<u16> for Voxel {
     fn eq(&self, other: &u16) -> bool {
         self.0 == *other
@@ -11,19 +30,29 @@ impl PartialEq<u16> for Voxel {
 
 impl From<Voxel> for f32 {
     fn from(value: Voxel) -> Self {
-        (Voxel::THRESHOLD_F32 - value.value() as f32) / Voxel::THRESHOLD_F32
+        value_to_f32(value.value())
     }
 }
 
+/// Convert the value of a voxel to its signed distance in the -1..=1 range, the same conversion as
+/// `f32::from(voxel)`. A value of 0 is air at 1, [Voxel::MAX_VALUE] is fully solid at -1, and the
+/// surface lies at [Voxel::THRESHOLD_F32]. Shaders that mesh voxels should use
+/// `(THRESHOLD_F32 - value) / THRESHOLD_F32` with the same constants to get identical surfaces
+pub fn value_to_f32(value: u16) -> f32 {
+    (Voxel::THRESHOLD_F32 - value as f32) / Voxel::THRESHOLD_F32
+}
+
 impl fast_surface_nets::SignedDistance for Voxel {
     fn is_negative(self) -> bool {
-        self.value() > Self::THRESHOLD
+        self.is_solid()
     }
 }
 
 impl Voxel {
-    const MATERIAL_BITS: u8 = 6;
-    const VALUE_BITS: u16 = 10;
+    /// The number of bits used for the material, stored in the high bits
+    pub const MATERIAL_BITS: u8 = 6;
+    /// The number of bits used for the value, stored in the low bits
+    pub const VALUE_BITS: u16 = 10;
 
     /// The total number of materials that are possible for a voxel
     pub const MATERIALS: u8 = 1 << Self::MATERIAL_BITS;
@@ -34,8 +63,11 @@ impl Voxel {
     pub const VALUES: u16 = 1 << Self::VALUE_BITS;
     /// The maximum value for a voxel
     pub const MAX_VALUE: u16 = Self::VALUES - 1;
+    /// The largest distance to the surface a voxel can store, in meters
+    pub const MAX_DISTANCE: f32 = 1.;
     const VALUE_MASK: u16 = Self::MAX_VALUE;
-    const THRESHOLD_F32: f32 = Self::MAX_VALUE as f32 / 2.;
+    /// The value at which the surface lies, values above it are solid
+    pub const THRESHOLD_F32: f32 = Self::MAX_VALUE as f32 / 2.;
     const THRESHOLD: u16 = Self::THRESHOLD_F32 as u16;
 
     /// An empty air voxel
@@ -49,6 +81,17 @@ impl Voxel {
         self.0
     }
 
+    /// Get the packed representation of this voxel. The material is stored in the high 6 bits and
+    /// the value in the low 10 bits. This is the stable representation voxels are stored with
+    pub fn to_u16(self) -> u16 {
+        self.0
+    }
+
+    /// Unpack a voxel from the representation returned by [Voxel::to_u16]
+    pub fn from_u16(input: u16) -> Self {
+        Self(input)
+    }
+
     /// Construct a Voxel from the specified material and value
     pub const fn new(material: u8, value: u16) -> Self {
         // TODO: Change these to debug-only branches
@@ -66,11 +109,13 @@ impl Voxel {
         (self.0 >> Self::VALUE_BITS) as u8
     }
 
-    /// Get a new Voxel with the specified sdf value
+    /// Get a new Voxel with the specified sdf value, values outside of the -1..=1 range are clamped
     pub fn with_value_f32(self, value: f32) -> Self {
         Self::new(
             self.material(),
-            (Voxel::THRESHOLD_F32 - (value * Voxel::THRESHOLD_F32)).round() as u16,
+            (Voxel::THRESHOLD_F32 - (value * Voxel::THRESHOLD_F32))
+                .round()
+                .clamp(0., Self::MAX_VALUE as f32) as u16,
         )
     }
 
@@ -78,6 +123,22 @@ impl Voxel {
     pub fn value(&self) -> u16 {
         self.0 & Self::VALUE_MASK
     }
+
+    /// Get the signed distance to the surface in meters, positive outside and negative inside.
+    ///
+    /// Edits evaluate their shapes in meters and store the distance clamped to `-1..=1`, so the
+    /// normalized value from `f32::from` already is the distance in meters, saturating at one
+    /// meter from the surface. [crate::VOXEL_SIZE] only affects the spacing between voxels:
+    /// `surface_nets` places the surface where the linear interpolation between two neighboring
+    /// voxels, [crate::VOXEL_SIZE] meters apart, crosses zero
+    pub fn sdf_meters(&self) -> f32 {
+        f32::from(*self) * Self::MAX_DISTANCE
+    }
+
+    /// Check if this voxel is on the inside of the surface
+    pub fn is_solid(&self) -> bool {
+        self.value() > Self::THRESHOLD
+    }
 }
 
 #[test]
@@ -93,3 +154,62 @@ fn test_set_value_f32() {
     assert_eq!(0, Voxel::AIR.with_value_f32(1.).value());
     assert_eq!(Voxel::MAX_VALUE, Voxel::AIR.with_value_f32(-1.).value());
 }
+
+#[test]
+fn test_set_value_f32_out_of_range() {
+    assert_eq!(0, Voxel::AIR.with_value_f32(5.).value());
+    assert_eq!(Voxel::MAX_VALUE, Voxel::AIR.with_value_f32(-5.).value());
+}
+
+#[test]
+fn test_u16_roundtrip() {
+    for voxel in [
+        Voxel::AIR,
+        Voxel::new(1, 300),
+        Voxel::new(Voxel::MAX_MATERIAL, 0),
+        Voxel::new(Voxel::MAX_MATERIAL, Voxel::MAX_VALUE),
+    ] {
+        assert_eq!(voxel, Voxel::from_u16(voxel.to_u16()));
+    }
+    assert_eq!((5 << 10) + 42, Voxel::new(5, 42).to_u16());
+}
+
+#[test]
+fn test_default_is_air() {
+    assert_eq!(Voxel::AIR, Voxel::default());
+}
+
+#[test]
+fn test_deserialize_masks() {
+    use serde::de::{value::U16Deserializer, IntoDeserializer};
+
+    let deserializer: U16Deserializer<serde::de::value::Error> = u16::MAX.into_deserializer();
+    let voxel = Voxel::deserialize(deserializer).unwrap();
+    assert_eq!(Voxel::MAX_MATERIAL, voxel.material());
+    assert_eq!(Voxel::MAX_VALUE, voxel.value());
+
+    let voxel: Voxel = serde_json::from_str("3113").unwrap();
+    assert_eq!(Voxel::new(3, 41), voxel);
+}
+
+#[test]
+fn test_sdf_meters() {
+    assert_eq!(Voxel::MAX_DISTANCE, Voxel::AIR.sdf_meters());
+    assert_eq!(
+        -Voxel::MAX_DISTANCE,
+        Voxel::new(0, Voxel::MAX_VALUE).sdf_meters()
+    );
+    assert!((Voxel::AIR.with_value_f32(0.3).sdf_meters() - 0.3).abs() < 0.002);
+}
+
+#[test]
+fn test_value_to_f32() {
+    for v in 0..=Voxel::MAX_VALUE {
+        assert_eq!(
+            f32::from(Voxel::new(0, v)).to_bits(),
+            value_to_f32(v).to_bits()
+        );
+    }
+    assert_eq!(1., value_to_f32(0));
+    assert_eq!(-1., value_to_f32(Voxel::MAX_VALUE));
+}